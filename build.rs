@@ -1,5 +1,5 @@
-use windres::Build;
-
 fn main() {
-    Build::new().compile("icons.rc").unwrap();
-}
\ No newline at end of file
+    // The tray icon resource is only used by the Windows build
+    #[cfg(windows)]
+    windres::Build::new().compile("icons.rc").unwrap();
+}
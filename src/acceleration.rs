@@ -0,0 +1,283 @@
+use std::time::Duration;
+use serde::Deserialize;
+use crate::error::{Error, Result};
+use crate::scale::VolumeScale;
+
+/// Maps the average time between volume key presses to the multiple of the
+/// base step a press should move the volume by
+pub trait AccelerationCurve: Send {
+    fn factor(&self, interval: Duration) -> f32;
+}
+
+/// Falls off from `max` at `fast_ms` apart to `min` at `slow_ms` apart
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Linear {
+    pub min: f32,
+    pub max: f32,
+    pub fast_ms: u64,
+    pub slow_ms: u64,
+}
+
+impl AccelerationCurve for Linear {
+    fn factor(&self, interval: Duration) -> f32 {
+        let ms = interval.as_millis() as f32;
+        let (fast, slow) = (self.fast_ms as f32, self.slow_ms as f32);
+        if ms <= fast {
+            return self.max;
+        }
+        if ms >= slow {
+            return self.min;
+        }
+        self.max + (self.min - self.max) * (ms - fast) / (slow - fast)
+    }
+}
+
+/// `max * exp(-decay_rate * ms)`, kept between `min` and `max`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Exponential {
+    pub min: f32,
+    pub max: f32,
+    pub decay_rate: f32,
+}
+
+impl Default for Exponential {
+    fn default() -> Self {
+        Self { min: 1.0, max: 15.0, decay_rate: 0.016 }
+    }
+}
+
+impl AccelerationCurve for Exponential {
+    fn factor(&self, interval: Duration) -> f32 {
+        let factor = self.max * (-self.decay_rate * interval.as_millis() as f32).exp();
+        factor.max(self.min).min(self.max)
+    }
+}
+
+/// A fixed factor for each band of intervals
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stepped {
+    /// Tried in order; the first step the interval is below wins
+    pub steps: Vec<Step>,
+    /// The factor for intervals no step covers
+    pub otherwise: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub below_ms: u64,
+    pub factor: f32,
+}
+
+impl AccelerationCurve for Stepped {
+    fn factor(&self, interval: Duration) -> f32 {
+        let ms = interval.as_millis() as u64;
+        self.steps.iter()
+            .find(|step| ms < step.below_ms)
+            .map_or(self.otherwise, |step| step.factor)
+    }
+}
+
+/// User-supplied `(ms, factor)` points, interpolated linearly in between and
+/// held flat beyond the first and last
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Table {
+    pub points: Vec<(u64, f32)>,
+}
+
+impl AccelerationCurve for Table {
+    fn factor(&self, interval: Duration) -> f32 {
+        let ms = interval.as_millis() as u64;
+        let Some(&(first_ms, first)) = self.points.first() else {
+            return 1.0;
+        };
+        if ms <= first_ms {
+            return first;
+        }
+
+        for pair in self.points.windows(2) {
+            let ((from_ms, from), (to_ms, to)) = (pair[0], pair[1]);
+            if ms <= to_ms {
+                let t = (ms - from_ms) as f32 / (to_ms - from_ms) as f32;
+                return from + (to - from) * t;
+            }
+        }
+        self.points.last().map_or(first, |&(_, last)| last)
+    }
+}
+
+/// A built-in curve, as chosen in the `[acceleration.curve]` table of the config file
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum CurveConfig {
+    Linear(Linear),
+    Exponential(Exponential),
+    Stepped(Stepped),
+    Table(Table),
+}
+
+impl Default for CurveConfig {
+    fn default() -> Self {
+        CurveConfig::Exponential(Exponential::default())
+    }
+}
+
+impl CurveConfig {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::InvalidConfig(message.to_string()));
+        match self {
+            CurveConfig::Linear(linear) if linear.fast_ms >= linear.slow_ms => invalid("Linear curve needs fast_ms below slow_ms"),
+            CurveConfig::Stepped(stepped) if !stepped.steps.is_sorted_by_key(|step| step.below_ms) => {
+                invalid("Stepped curve needs its steps in order of below_ms")
+            }
+            CurveConfig::Table(table) if table.points.is_empty() => invalid("Table curve needs at least one point"),
+            CurveConfig::Table(table) if !table.points.windows(2).all(|pair| pair[0].0 < pair[1].0) => {
+                invalid("Table curve needs its points in increasing order of ms")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn AccelerationCurve> {
+        match self.clone() {
+            CurveConfig::Linear(curve) => Box::new(curve),
+            CurveConfig::Exponential(curve) => Box::new(curve),
+            CurveConfig::Stepped(curve) => Box::new(curve),
+            CurveConfig::Table(curve) => Box::new(curve),
+        }
+    }
+}
+
+/// What happens to the press history when the volume keys change direction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reversal {
+    /// Start over, so the first press the other way makes the base step
+    #[default]
+    Reset,
+    /// Keep the history with every interval stretched by `1 / damp`, so some of the speed carries over
+    Damp(f32),
+}
+
+/// Settings read from the `[acceleration]` table of the config file
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccelerationConfig {
+    /// How far one unaccelerated press moves the volume, out of 1.0 on `scale`
+    pub base_increment: f32,
+    pub scale: VolumeScale,
+    /// How many of the latest intervals between presses are averaged
+    pub history: usize,
+    pub curve: CurveConfig,
+    pub on_reversal: Reversal,
+    /// A press this long after the one before starts over, whichever way it goes
+    pub idle_reset_ms: u64,
+    /// How fast holding a volume key moves the volume, out of 1.0 per second.
+    /// When unset, each autorepeat of a held key counts as a press.
+    pub ramp_per_sec: Option<f32>,
+    /// How long a key has to be held before the ramp starts
+    pub ramp_delay_ms: u64,
+}
+
+impl Default for AccelerationConfig {
+    fn default() -> Self {
+        Self {
+            base_increment: 0.01,
+            scale: VolumeScale::Linear,
+            history: 5,
+            curve: CurveConfig::default(),
+            on_reversal: Reversal::Reset,
+            idle_reset_ms: 1500,
+            ramp_per_sec: None,
+            ramp_delay_ms: 300,
+        }
+    }
+}
+
+impl AccelerationConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.history == 0 {
+            return Err(Error::InvalidConfig("Acceleration history must be at least 1".to_string()));
+        }
+        if let Reversal::Damp(damp) = self.on_reversal && !(damp > 0.0 && damp <= 1.0) {
+            return Err(Error::InvalidConfig(format!("Reversal damping must be above 0 and at most 1, not {}", damp)));
+        }
+        if let Some(rate) = self.ramp_per_sec && rate <= 0.0 {
+            return Err(Error::InvalidConfig(format!("Ramp rate must be above 0, not {}", rate)));
+        }
+        self.scale.validate()?;
+        self.curve.validate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn factors(curve: &dyn AccelerationCurve, intervals_ms: &[u64]) -> Vec<f32> {
+        intervals_ms.iter().map(|&ms| curve.factor(Duration::from_millis(ms))).collect()
+    }
+
+    fn assert_close(actual: Vec<f32>, expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-3, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn linear_curve_falls_off_between_fast_and_slow() {
+        let curve = Linear { min: 1.0, max: 5.0, fast_ms: 100, slow_ms: 500 };
+        assert_close(factors(&curve, &[0, 100, 200, 300, 499, 500, 5000]), &[5.0, 5.0, 4.0, 3.0, 1.01, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn exponential_curve_matches_the_original_formula() {
+        let curve = Exponential::default();
+        let expected: Vec<f32> = [50u64, 100, 150]
+            .iter()
+            .map(|&ms| 15.0 * (-0.016 * ms as f32).exp())
+            .collect();
+        assert_close(factors(&curve, &[50, 100, 150]), &expected);
+
+        // Clamped at both ends
+        assert_close(factors(&curve, &[0, 1000]), &[15.0, 1.0]);
+    }
+
+    #[test]
+    fn stepped_curve_picks_the_first_band_the_interval_is_below() {
+        let curve = Stepped {
+            steps: vec![Step { below_ms: 100, factor: 8.0 }, Step { below_ms: 300, factor: 3.0 }],
+            otherwise: 1.0,
+        };
+        assert_close(factors(&curve, &[0, 99, 100, 299, 300, 2000]), &[8.0, 8.0, 3.0, 3.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn table_curve_interpolates_between_points() {
+        let curve = Table { points: vec![(50, 10.0), (150, 4.0), (650, 1.5)] };
+        assert_close(factors(&curve, &[0, 50, 100, 150, 400, 650, 1000]), &[10.0, 10.0, 7.0, 4.0, 2.75, 1.5, 1.5]);
+
+        let single = Table { points: vec![(100, 2.0)] };
+        assert_close(factors(&single, &[0, 100, 1000]), &[2.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn rejects_curves_that_cannot_be_evaluated() {
+        let cases = [
+            CurveConfig::Linear(Linear { min: 1.0, max: 4.0, fast_ms: 300, slow_ms: 300 }),
+            CurveConfig::Stepped(Stepped { steps: vec![Step { below_ms: 300, factor: 2.0 }, Step { below_ms: 100, factor: 4.0 }], otherwise: 1.0 }),
+            CurveConfig::Table(Table { points: Vec::new() }),
+            CurveConfig::Table(Table { points: vec![(100, 2.0), (100, 1.0)] }),
+        ];
+
+        for curve in cases {
+            assert!(matches!(curve.validate(), Err(Error::InvalidConfig(_))), "{:?}", curve);
+        }
+        assert!(CurveConfig::default().validate().is_ok());
+    }
+}
@@ -1,138 +1,284 @@
-use windows::Win32::Media::Audio::*;
-use windows::Win32::System::Com::CLSCTX_ALL;
-use windows::core::ComInterface;
-use crate::focus;
-
-
-pub fn get_session_by_process_path(process_path: &str) -> Result<IAudioSessionControl2, Box<dyn std::error::Error>> {
-    unsafe {
-        // Initialize COM library
-        windows::Win32::System::Com::CoInitializeEx(None, windows::Win32::System::Com::COINIT_APARTMENTTHREADED)?;
-
-        // Create a multimedia device enumerator
-        let enumerator: IMMDeviceEnumerator = windows::Win32::System::Com::CoCreateInstance(&MMDeviceEnumerator, None, windows::Win32::System::Com::CLSCTX_ALL)?;
-
-        // Get the default audio endpoint
-        let device = enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?;
-
-        // Activate the audio session manager
-        let session_manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, Some(std::ptr::null_mut()))?;
-
-        // Get the audio session enumerator
-        let session_enumerator = session_manager.GetSessionEnumerator()?;
-
-        // Get the number of audio sessions
-        let count = session_enumerator.GetCount()?;
-
-        for i in 0..count {
-            // Get the basic session control
-            let session_control = session_enumerator.GetSession(i)?;
-            
-            // Use the cast method to convert to IAudioSessionControl2
-            let session_control2: IAudioSessionControl2 = session_control.cast()?;
-            
-            // Get the process ID of the session
-            let session_pid = session_control2.GetProcessId()?;
-            
-            if session_pid == 0 {
-                // Skip the system session
-                // (in the future we can handle this)
-                continue;
-            }
-
-            // Get the process path of the session
-            let session_path = focus::get_process_path(session_pid)?;
-            if session_path == process_path {
-                return Ok(session_control2);
-            }
+pub mod mock;
+#[cfg(all(target_os = "linux", feature = "pulse"))]
+pub mod pulse;
+pub mod registry;
+#[cfg(all(windows, feature = "win32"))]
+pub mod wasapi;
+
+pub use mock::{MockAudioBackend, MockSession};
+pub use registry::{RegisteredSession, SessionRegistry};
+#[cfg(all(windows, feature = "win32"))]
+pub use wasapi::WasapiBackend;
+#[cfg(all(target_os = "linux", feature = "pulse"))]
+pub use pulse::PulseBackend;
+
+use crate::error::{Error, Result};
+use crate::scale::VolumeScale;
+
+/// A change to the set of sessions, reported by backends that can watch for them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionEvent<S> {
+    Added(S),
+    /// The session expired or its device went away
+    Removed(S),
+}
+
+/// An audio system that exposes per-application sessions.
+///
+/// `Session` is an opaque handle owned by the backend; callers only pass it
+/// back into the backend's own methods. Two handles compare equal when they
+/// refer to the same session.
+pub trait AudioBackend {
+    type Session: Clone + PartialEq;
+
+    /// Lists every session currently known to the backend
+    fn sessions(&self) -> Result<Vec<Self::Session>>;
+
+    /// Gets the ID of the process that owns the session (0 for the system session)
+    fn session_pid(&self, session: &Self::Session) -> Result<u32>;
+
+    /// Gets the executable path of the process that owns the session
+    fn session_path(&self, session: &Self::Session) -> Result<String>;
+
+    /// Gets the application name the session reports, if the backend has one
+    fn session_name(&self, _session: &Self::Session) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Gets the volume of the session as a float between 0.0 and 1.0
+    fn volume(&self, session: &Self::Session) -> Result<f32>;
+
+    fn set_volume(&self, session: &Self::Session, volume: f32) -> Result<()>;
+
+    fn is_muted(&self, session: &Self::Session) -> Result<bool>;
+
+    fn set_muted(&self, session: &Self::Session, muted: bool) -> Result<()>;
+
+    /// Whether the session is making sound right now
+    fn is_playing(&self, _session: &Self::Session) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Gets the volume of the default output device as a float between 0.0 and 1.0
+    fn master_volume(&self) -> Result<f32> {
+        Err(Error::Unsupported("master volume"))
+    }
+
+    fn set_master_volume(&self, _volume: f32) -> Result<()> {
+        Err(Error::Unsupported("master volume"))
+    }
+
+    fn is_master_muted(&self) -> Result<bool> {
+        Err(Error::Unsupported("master mute"))
+    }
+
+    fn set_master_muted(&self, _muted: bool) -> Result<()> {
+        Err(Error::Unsupported("master mute"))
+    }
+
+    /// Starts watching for sessions being created and expiring.
+    ///
+    /// Returns false if the backend can't, in which case callers have to
+    /// rescan `sessions` to notice changes.
+    fn watch_sessions(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Takes the events received since the last call
+    fn session_events(&self) -> Result<Vec<SessionEvent<Self::Session>>> {
+        Ok(Vec::new())
+    }
+
+    /// Whether the default output device has changed since the backend was
+    /// opened, for backends bound to one device. Such a backend has to be
+    /// reopened to follow the new default.
+    fn device_changed(&self) -> bool {
+        false
+    }
+}
+
+
+pub fn get_session_by_process_path<B: AudioBackend>(backend: &B, process_path: &str) -> Result<B::Session> {
+    for session in backend.sessions()? {
+        // Get the process ID of the session
+        let session_pid = backend.session_pid(&session)?;
+
+        if session_pid == 0 {
+            // Skip the system session
+            // (in the future we can handle this)
+            continue;
         }
 
-        Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "Session not found")))
+        // Get the process path of the session, skipping processes we aren't allowed to open
+        let session_path = match backend.session_path(&session) {
+            Ok(path) => path,
+            Err(Error::ProcessAccessDenied { .. }) => continue,
+            Err(e) => return Err(e),
+        };
+        if session_path == process_path {
+            return Ok(session);
+        }
     }
-}   
+
+    Err(Error::SessionNotFound { path: process_path.to_string() })
+}
 
 
 /// Gets the master volume level for a specific audio session
-/// 
+///
 /// Returns a float between 0.0 (muted) and 1.0 (full volume)
-pub fn get_session_volume(session_control: &IAudioSessionControl2) -> Result<f32, Box<dyn std::error::Error>> {
-    unsafe {
-        // Get the simple audio volume interface from the session control
-        let simple_audio_volume: ISimpleAudioVolume = session_control.cast()?;
-        
-        // Get the current master volume level directly from the method return
-        let volume_level = simple_audio_volume.GetMasterVolume()?;
-        
-        Ok(volume_level)
-    }
+pub fn get_session_volume<B: AudioBackend>(backend: &B, session: &B::Session) -> Result<f32> {
+    backend.volume(session)
 }
 
-pub fn set_session_volume(session_control: &IAudioSessionControl2, volume: f32) -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        // Get the simple audio volume interface from the session control
-        let simple_audio_volume: ISimpleAudioVolume = session_control.cast()?;
+pub fn set_session_volume<B: AudioBackend>(backend: &B, session: &B::Session, volume: f32) -> Result<()> {
+    backend.set_volume(session, volume)
+}
 
-        // Set the master volume level
-        simple_audio_volume.SetMasterVolume(volume, std::ptr::null())?;
 
-        Ok(())
-    }
+pub fn mute_session<B: AudioBackend>(backend: &B, session: &B::Session) -> Result<()> {
+    backend.set_muted(session, true)
 }
 
+pub fn unmute_session<B: AudioBackend>(backend: &B, session: &B::Session) -> Result<()> {
+    backend.set_muted(session, false)
+}
 
-pub fn mute_session(session_control: &IAudioSessionControl2) -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        // Get the simple audio volume interface from the session control
-        let simple_audio_volume: ISimpleAudioVolume = session_control.cast()?;
+pub fn toggle_session_mute<B: AudioBackend>(backend: &B, session: &B::Session) -> Result<()> {
+    // Toggle the mute state
+    let current_mute = backend.is_muted(session)?;
+    backend.set_muted(session, !current_mute)
+}
 
-        // Mute the session
-        simple_audio_volume.SetMute(true, std::ptr::null())?;
 
-        Ok(())
-    }
+
+pub fn increment_session_volume<B: AudioBackend>(backend: &B, session: &B::Session, increment: f32) -> Result<f32> {
+    increment_sessions_volume(backend, std::slice::from_ref(session), increment)
 }
 
-pub fn unmute_session(session_control: &IAudioSessionControl2) -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        // Get the simple audio volume interface from the session control
-        let simple_audio_volume: ISimpleAudioVolume = session_control.cast()?;
+pub fn decrement_session_volume<B: AudioBackend>(backend: &B, session: &B::Session, decrement: f32) -> Result<f32> {
+    decrement_sessions_volume(backend, std::slice::from_ref(session), decrement)
+}
 
-        // Unmute the session
-        simple_audio_volume.SetMute(false, std::ptr::null())?;
 
-        Ok(())
-    }
+/// Gets the volume of a group of sessions, which is that of the loudest one
+pub fn get_sessions_volume<B: AudioBackend>(backend: &B, sessions: &[B::Session]) -> Result<f32> {
+    Ok(loudest(&get_volumes(backend, sessions)?))
+}
+
+/// Moves the loudest session of the group to `volume`, scaling the others so
+/// their levels relative to it stay the same. Returns the new group volume.
+pub fn set_sessions_volume<B: AudioBackend>(backend: &B, sessions: &[B::Session], volume: f32) -> Result<f32> {
+    let current = get_volumes(backend, sessions)?;
+    scale_volumes(backend, sessions, &current, volume)
+}
+
+pub fn increment_sessions_volume<B: AudioBackend>(backend: &B, sessions: &[B::Session], increment: f32) -> Result<f32> {
+    step_sessions_volume(backend, sessions, VolumeScale::Linear, increment)
+}
+
+pub fn decrement_sessions_volume<B: AudioBackend>(backend: &B, sessions: &[B::Session], decrement: f32) -> Result<f32> {
+    step_sessions_volume(backend, sessions, VolumeScale::Linear, -decrement)
+}
+
+/// Moves the group volume by `delta` along `scale`, scaling the sessions as
+/// `set_sessions_volume` does. Returns the new group volume.
+pub fn step_sessions_volume<B: AudioBackend>(backend: &B, sessions: &[B::Session], scale: VolumeScale, delta: f32) -> Result<f32> {
+    let current = get_volumes(backend, sessions)?;
+    scale_volumes(backend, sessions, &current, scale.step(loudest(&current), delta))
+}
+
+fn get_volumes<B: AudioBackend>(backend: &B, sessions: &[B::Session]) -> Result<Vec<f32>> {
+    sessions.iter().map(|session| backend.volume(session)).collect()
+}
+
+fn loudest(volumes: &[f32]) -> f32 {
+    volumes.iter().copied().fold(0.0, f32::max)
 }
 
-pub fn toggle_session_mute(session_control: &IAudioSessionControl2) -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        // Get the simple audio volume interface from the session control
-        let simple_audio_volume: ISimpleAudioVolume = session_control.cast()?;
+fn scale_volumes<B: AudioBackend>(backend: &B, sessions: &[B::Session], current: &[f32], volume: f32) -> Result<f32> {
+    let volume = volume.clamp(0.0, 1.0);
+    let loudest = loudest(current);
 
-        // Toggle the mute state
-        let current_mute = simple_audio_volume.GetMute()?;
-        simple_audio_volume.SetMute(!current_mute, std::ptr::null())?;
+    for (session, &session_volume) in sessions.iter().zip(current) {
+        // A silent group has no levels to keep, so every session starts over from the same one
+        let new_volume = if loudest > 0.0 { session_volume * volume / loudest } else { volume };
+        backend.set_volume(session, new_volume.clamp(0.0, 1.0))?;
+    }
+
+    Ok(volume)
+}
 
-        Ok(())
+/// A group of sessions only counts as muted when every session in it is
+pub fn are_sessions_muted<B: AudioBackend>(backend: &B, sessions: &[B::Session]) -> Result<bool> {
+    for session in sessions {
+        if !backend.is_muted(session)? {
+            return Ok(false);
+        }
     }
+    Ok(!sessions.is_empty())
 }
 
+/// Mutes the whole group unless all of it is already muted, in which case it is unmuted
+pub fn toggle_sessions_mute<B: AudioBackend>(backend: &B, sessions: &[B::Session]) -> Result<bool> {
+    let muted = !are_sessions_muted(backend, sessions)?;
+    for session in sessions {
+        backend.set_muted(session, muted)?;
+    }
+    Ok(muted)
+}
 
+/// Mutes every session in `sessions` except those in `keep`, which are
+/// unmuted. Returns the mute states from before, for `restore_mute_states`.
+pub fn solo_sessions<B: AudioBackend>(backend: &B, sessions: &[B::Session], keep: &[B::Session]) -> Result<Vec<(B::Session, bool)>> {
+    let mut saved = Vec::with_capacity(sessions.len());
+    for session in sessions {
+        // A session that closed while the list was being walked has nothing to restore
+        match backend.is_muted(session) {
+            Ok(muted) => saved.push((session.clone(), muted)),
+            Err(Error::SessionExpired) => continue,
+            Err(e) => return Err(e),
+        }
+    }
 
-pub fn increment_session_volume(session_control: &IAudioSessionControl2, increment: f32) -> Result<f32, Box<dyn std::error::Error>> {
-    let current_volume = get_session_volume(session_control)?;
-    let new_volume = current_volume + increment;
-    let new_volume = if new_volume > 1.0 { 1.0 } else { new_volume };
+    for (session, _) in &saved {
+        match backend.set_muted(session, !keep.contains(session)) {
+            Ok(()) | Err(Error::SessionExpired) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(saved)
+}
 
-    set_session_volume(session_control, new_volume)?;
-    Ok(new_volume)
+/// Puts back the mute states `solo_sessions` saved, skipping sessions that have closed since
+pub fn restore_mute_states<B: AudioBackend>(backend: &B, saved: &[(B::Session, bool)]) -> Result<()> {
+    for (session, muted) in saved {
+        match backend.set_muted(session, *muted) {
+            Ok(()) | Err(Error::SessionExpired) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
 }
 
-pub fn decrement_session_volume(session_control: &IAudioSessionControl2, decrement: f32) -> Result<f32, Box<dyn std::error::Error>> {
-    let current_volume = get_session_volume(session_control)?;
-    let new_volume = current_volume - decrement;
-    let new_volume = if new_volume < 0.0 { 0.0 } else { new_volume };
+pub fn increment_master_volume<B: AudioBackend>(backend: &B, increment: f32) -> Result<f32> {
+    step_master_volume(backend, VolumeScale::Linear, increment)
+}
 
-    set_session_volume(session_control, new_volume)?;
+pub fn decrement_master_volume<B: AudioBackend>(backend: &B, decrement: f32) -> Result<f32> {
+    step_master_volume(backend, VolumeScale::Linear, -decrement)
+}
+
+/// Moves the default output device's volume by `delta` along `scale`
+pub fn step_master_volume<B: AudioBackend>(backend: &B, scale: VolumeScale, delta: f32) -> Result<f32> {
+    let new_volume = scale.step(backend.master_volume()?, delta);
+    backend.set_master_volume(new_volume)?;
     Ok(new_volume)
 }
+
+/// Toggles the default output device's mute, returning whether it is now muted
+pub fn toggle_master_mute<B: AudioBackend>(backend: &B) -> Result<bool> {
+    let muted = !backend.is_master_muted()?;
+    backend.set_master_muted(muted)?;
+    Ok(muted)
+}
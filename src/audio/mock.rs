@@ -0,0 +1,386 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::audio::{AudioBackend, SessionEvent};
+use crate::error::{Error, Result};
+
+/// Handle to a session living inside a `MockAudioBackend`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MockSession(usize);
+
+#[derive(Clone, Debug)]
+struct MockSessionState {
+    pid: u32,
+    path: String,
+    name: Option<String>,
+    volume: f32,
+    muted: bool,
+    playing: bool,
+    active: bool,
+    access_denied: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct MockMaster {
+    volume: f32,
+    muted: bool,
+}
+
+impl Default for MockMaster {
+    fn default() -> Self {
+        Self { volume: 1.0, muted: false }
+    }
+}
+
+#[derive(Default)]
+struct MockEvents {
+    unsupported: bool,
+    failing: bool,
+    watching: bool,
+    queue: Vec<SessionEvent<MockSession>>,
+}
+
+/// In-memory audio backend whose sessions can be scripted from tests.
+///
+/// Clones share the same sessions, so a test can keep one copy for
+/// inspection while the code under test owns another. Once watched, adding
+/// and removing sessions queues the matching `SessionEvent`s.
+#[derive(Clone, Default)]
+pub struct MockAudioBackend {
+    sessions: Arc<Mutex<Vec<MockSessionState>>>,
+    events: Arc<Mutex<MockEvents>>,
+    master: Arc<Mutex<MockMaster>>,
+    scans: Arc<AtomicUsize>,
+}
+
+impl MockAudioBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a session at full volume, unmuted and silent
+    pub fn add_session(&self, pid: u32, path: &str) -> MockSession {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.push(MockSessionState {
+            pid,
+            path: path.to_string(),
+            name: None,
+            volume: 1.0,
+            muted: false,
+            playing: false,
+            active: true,
+            access_denied: false,
+        });
+        let session = MockSession(sessions.len() - 1);
+        self.emit(SessionEvent::Added(session));
+        session
+    }
+
+    /// Removes a session so it is no longer enumerated
+    pub fn remove_session(&self, session: MockSession) {
+        self.sessions.lock().unwrap()[session.0].active = false;
+        self.emit(SessionEvent::Removed(session));
+    }
+
+    /// Reports a session as added again, like a notification racing a scan
+    pub fn emit_added(&self, session: MockSession) {
+        self.emit(SessionEvent::Added(session));
+    }
+
+    /// Makes `watch_sessions` fail to start, like a backend without notifications
+    pub fn disable_notifications(&self) {
+        self.events.lock().unwrap().unsupported = true;
+    }
+
+    /// Makes `watch_sessions` return an error, like a session manager that refuses to register
+    pub fn fail_notifications(&self) {
+        self.events.lock().unwrap().failing = true;
+    }
+
+    /// Number of times the sessions have been enumerated
+    pub fn scan_count(&self) -> usize {
+        self.scans.load(Ordering::SeqCst)
+    }
+
+    pub fn volume_of(&self, session: MockSession) -> f32 {
+        self.sessions.lock().unwrap()[session.0].volume
+    }
+
+    pub fn set_volume_of(&self, session: MockSession, volume: f32) {
+        self.sessions.lock().unwrap()[session.0].volume = volume;
+    }
+
+    pub fn muted_of(&self, session: MockSession) -> bool {
+        self.sessions.lock().unwrap()[session.0].muted
+    }
+
+    pub fn set_muted_of(&self, session: MockSession, muted: bool) {
+        self.sessions.lock().unwrap()[session.0].muted = muted;
+    }
+
+    /// Sets whether the session is making sound
+    pub fn set_playing_of(&self, session: MockSession, playing: bool) {
+        self.sessions.lock().unwrap()[session.0].playing = playing;
+    }
+
+    pub fn master_volume_of(&self) -> f32 {
+        self.master.lock().unwrap().volume
+    }
+
+    pub fn set_master_volume_of(&self, volume: f32) {
+        self.master.lock().unwrap().volume = volume;
+    }
+
+    pub fn master_muted_of(&self) -> bool {
+        self.master.lock().unwrap().muted
+    }
+
+    /// Sets the application name the session reports
+    pub fn set_name_of(&self, session: MockSession, name: &str) {
+        self.sessions.lock().unwrap()[session.0].name = Some(name.to_string());
+    }
+
+    /// Makes reading the session's process path fail as if the process were elevated
+    pub fn deny_access(&self, session: MockSession) {
+        self.sessions.lock().unwrap()[session.0].access_denied = true;
+    }
+
+    fn emit(&self, event: SessionEvent<MockSession>) {
+        let mut events = self.events.lock().unwrap();
+        if events.watching {
+            events.queue.push(event);
+        }
+    }
+
+    fn with_session<T>(&self, session: &MockSession, f: impl FnOnce(&mut MockSessionState) -> T) -> Result<T> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get_mut(session.0) {
+            Some(state) if state.active => Ok(f(state)),
+            _ => Err(Error::SessionExpired),
+        }
+    }
+}
+
+impl AudioBackend for MockAudioBackend {
+    type Session = MockSession;
+
+    fn sessions(&self) -> Result<Vec<MockSession>> {
+        self.scans.fetch_add(1, Ordering::SeqCst);
+        let sessions = self.sessions.lock().unwrap();
+        Ok((0..sessions.len())
+            .filter(|&i| sessions[i].active)
+            .map(MockSession)
+            .collect())
+    }
+
+    fn session_pid(&self, session: &MockSession) -> Result<u32> {
+        self.with_session(session, |state| state.pid)
+    }
+
+    fn session_path(&self, session: &MockSession) -> Result<String> {
+        let (pid, path, access_denied) = self.with_session(session, |state| (state.pid, state.path.clone(), state.access_denied))?;
+        if access_denied {
+            return Err(Error::ProcessAccessDenied { pid });
+        }
+        Ok(path)
+    }
+
+    fn session_name(&self, session: &MockSession) -> Result<Option<String>> {
+        self.with_session(session, |state| state.name.clone())
+    }
+
+    fn volume(&self, session: &MockSession) -> Result<f32> {
+        self.with_session(session, |state| state.volume)
+    }
+
+    fn set_volume(&self, session: &MockSession, volume: f32) -> Result<()> {
+        self.with_session(session, |state| state.volume = volume)
+    }
+
+    fn is_muted(&self, session: &MockSession) -> Result<bool> {
+        self.with_session(session, |state| state.muted)
+    }
+
+    fn set_muted(&self, session: &MockSession, muted: bool) -> Result<()> {
+        self.with_session(session, |state| state.muted = muted)
+    }
+
+    fn is_playing(&self, session: &MockSession) -> Result<bool> {
+        self.with_session(session, |state| state.playing)
+    }
+
+    fn master_volume(&self) -> Result<f32> {
+        Ok(self.master.lock().unwrap().volume)
+    }
+
+    fn set_master_volume(&self, volume: f32) -> Result<()> {
+        self.master.lock().unwrap().volume = volume;
+        Ok(())
+    }
+
+    fn is_master_muted(&self) -> Result<bool> {
+        Ok(self.master.lock().unwrap().muted)
+    }
+
+    fn set_master_muted(&self, muted: bool) -> Result<()> {
+        self.master.lock().unwrap().muted = muted;
+        Ok(())
+    }
+
+    fn watch_sessions(&self) -> Result<bool> {
+        let mut events = self.events.lock().unwrap();
+        if events.failing {
+            return Err(Error::Backend("Session notifications failed to register".into()));
+        }
+        events.watching = !events.unsupported;
+        Ok(events.watching)
+    }
+
+    fn session_events(&self) -> Result<Vec<SessionEvent<MockSession>>> {
+        Ok(std::mem::take(&mut self.events.lock().unwrap().queue))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio;
+
+    #[test]
+    fn finds_session_by_path_and_skips_system_session() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(0, "");
+        let spotify = backend.add_session(42, "C:\\Spotify\\Spotify.exe");
+
+        let found = audio::get_session_by_process_path(&backend, "C:\\Spotify\\Spotify.exe").unwrap();
+        assert_eq!(found, spotify);
+        assert!(matches!(
+            audio::get_session_by_process_path(&backend, "C:\\Other.exe"),
+            Err(Error::SessionNotFound { path }) if path == "C:\\Other.exe"
+        ));
+    }
+
+    #[test]
+    fn skips_sessions_of_processes_that_cannot_be_opened() {
+        let backend = MockAudioBackend::new();
+        let elevated = backend.add_session(5, "C:\\Admin\\Tool.exe");
+        backend.deny_access(elevated);
+        let spotify = backend.add_session(42, "C:\\Spotify\\Spotify.exe");
+
+        assert_eq!(audio::get_session_by_process_path(&backend, "C:\\Spotify\\Spotify.exe").unwrap(), spotify);
+    }
+
+    #[test]
+    fn removed_sessions_are_not_found() {
+        let backend = MockAudioBackend::new();
+        let session = backend.add_session(7, "/usr/bin/mpv");
+        backend.remove_session(session);
+
+        assert!(audio::get_session_by_process_path(&backend, "/usr/bin/mpv").is_err());
+        assert!(matches!(audio::get_session_volume(&backend, &session), Err(Error::SessionExpired)));
+    }
+
+    #[test]
+    fn volume_changes_are_clamped() {
+        let backend = MockAudioBackend::new();
+        let session = backend.add_session(7, "/usr/bin/mpv");
+        backend.set_volume_of(session, 0.95);
+
+        assert_eq!(audio::increment_session_volume(&backend, &session, 0.1).unwrap(), 1.0);
+        backend.set_volume_of(session, 0.05);
+        assert_eq!(audio::decrement_session_volume(&backend, &session, 0.1).unwrap(), 0.0);
+        assert_eq!(backend.volume_of(session), 0.0);
+    }
+
+    #[test]
+    fn group_volume_changes_keep_relative_levels() {
+        let backend = MockAudioBackend::new();
+        let loud = backend.add_session(7, "/usr/bin/game");
+        let quiet = backend.add_session(8, "/usr/bin/game");
+        backend.set_volume_of(loud, 0.8);
+        backend.set_volume_of(quiet, 0.2);
+        let group = [loud, quiet];
+
+        assert_eq!(audio::get_sessions_volume(&backend, &group).unwrap(), 0.8);
+        assert_eq!(audio::decrement_sessions_volume(&backend, &group, 0.4).unwrap(), 0.4);
+        assert!((backend.volume_of(loud) - 0.4).abs() < 1e-6);
+        assert!((backend.volume_of(quiet) - 0.1).abs() < 1e-6);
+
+        // The loudest session is clamped at full volume and the rest scale with it
+        assert_eq!(audio::increment_sessions_volume(&backend, &group, 0.9).unwrap(), 1.0);
+        assert!((backend.volume_of(quiet) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn silent_groups_are_raised_together() {
+        let backend = MockAudioBackend::new();
+        let first = backend.add_session(7, "/usr/bin/game");
+        let second = backend.add_session(8, "/usr/bin/game");
+        backend.set_volume_of(first, 0.0);
+        backend.set_volume_of(second, 0.0);
+
+        audio::increment_sessions_volume(&backend, &[first, second], 0.1).unwrap();
+        assert_eq!(backend.volume_of(first), 0.1);
+        assert_eq!(backend.volume_of(second), 0.1);
+    }
+
+    #[test]
+    fn group_mute_follows_combined_state() {
+        let backend = MockAudioBackend::new();
+        let first = backend.add_session(7, "/usr/bin/game");
+        let second = backend.add_session(8, "/usr/bin/game");
+        backend.set_muted_of(second, true);
+        let group = [first, second];
+
+        assert!(!audio::are_sessions_muted(&backend, &group).unwrap());
+        assert!(audio::toggle_sessions_mute(&backend, &group).unwrap());
+        assert!(backend.muted_of(first) && backend.muted_of(second));
+        assert!(!audio::toggle_sessions_mute(&backend, &group).unwrap());
+        assert!(!backend.muted_of(first) && !backend.muted_of(second));
+    }
+
+    #[test]
+    fn toggles_mute() {
+        let backend = MockAudioBackend::new();
+        let session = backend.add_session(7, "/usr/bin/mpv");
+
+        audio::toggle_session_mute(&backend, &session).unwrap();
+        assert!(backend.muted_of(session));
+        audio::toggle_session_mute(&backend, &session).unwrap();
+        assert!(!backend.muted_of(session));
+    }
+
+    #[test]
+    fn master_volume_changes_are_clamped() {
+        let backend = MockAudioBackend::new();
+        backend.set_master_volume_of(0.95);
+
+        assert_eq!(audio::increment_master_volume(&backend, 0.1).unwrap(), 1.0);
+        assert_eq!(audio::decrement_master_volume(&backend, 0.25).unwrap(), 0.75);
+        assert!(audio::toggle_master_mute(&backend).unwrap());
+        assert!(backend.master_muted_of());
+    }
+
+    #[test]
+    fn solo_restores_the_previous_mute_states() {
+        let backend = MockAudioBackend::new();
+        let focused = backend.add_session(7, "/usr/bin/mpv");
+        let playing = backend.add_session(8, "/usr/bin/spotify");
+        let already_muted = backend.add_session(9, "/usr/bin/game");
+        let closing = backend.add_session(10, "/usr/bin/chat");
+        backend.set_muted_of(focused, true);
+        backend.set_muted_of(already_muted, true);
+        let sessions = backend.sessions().unwrap();
+
+        let saved = audio::solo_sessions(&backend, &sessions, &[focused]).unwrap();
+        assert!(!backend.muted_of(focused), "the soloed session should be audible");
+        assert!(backend.muted_of(playing) && backend.muted_of(already_muted) && backend.muted_of(closing));
+
+        // Changes made during the solo are overwritten, closed sessions are skipped
+        backend.set_muted_of(already_muted, false);
+        backend.remove_session(closing);
+        audio::restore_mute_states(&backend, &saved).unwrap();
+        assert!(backend.muted_of(focused));
+        assert!(!backend.muted_of(playing));
+        assert!(backend.muted_of(already_muted));
+    }
+}
@@ -0,0 +1,509 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use libpulse_binding::callbacks::ListResult;
+use libpulse_binding::context::{Context, FlagSet as ContextFlagSet, State as ContextState};
+use libpulse_binding::context::introspect::SinkInputInfo;
+use libpulse_binding::context::subscribe::{Facility, InterestMaskSet, Operation as SubscribeOperation};
+use libpulse_binding::mainloop::standard::{IterateResult, Mainloop};
+use libpulse_binding::operation::{Operation, State as OperationState};
+use libpulse_binding::proplist::properties;
+use libpulse_binding::volume::{ChannelVolumes, Volume};
+use crate::audio::{AudioBackend, SessionEvent};
+use crate::error::{Error, Result};
+
+/// A PulseAudio sink-input, the equivalent of a WASAPI audio session.
+///
+/// The process details are captured when the sessions are listed, while
+/// volume and mute state are always read from the server. Sessions compare
+/// equal by sink-input index.
+#[derive(Clone, Debug)]
+pub struct PulseSession {
+    index: u32,
+    pid: Option<u32>,
+    binary: Option<String>,
+    name: Option<String>,
+}
+
+impl PulseSession {
+    fn from_info(info: &SinkInputInfo) -> Self {
+        let proplist = &info.proplist;
+        Self {
+            index: info.index,
+            pid: proplist.get_str(properties::APPLICATION_PROCESS_ID).and_then(|pid| pid.parse().ok()),
+            binary: proplist.get_str(properties::APPLICATION_PROCESS_BINARY),
+            name: proplist.get_str(properties::APPLICATION_NAME),
+        }
+    }
+
+    // Removal events only carry the index, which is all equality looks at
+    fn from_index(index: u32) -> Self {
+        Self {
+            index,
+            pid: None,
+            binary: None,
+            name: None,
+        }
+    }
+}
+
+impl PartialEq for PulseSession {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl Eq for PulseSession {}
+
+const DEFAULT_SINK: &str = "@DEFAULT_SINK@";
+
+struct StreamState {
+    volumes: ChannelVolumes,
+    muted: bool,
+    corked: bool,
+}
+
+/// Audio backend for PulseAudio, including PipeWire's PulseAudio server.
+///
+/// Every call is driven to completion on a private mainloop, so the backend
+/// behaves synchronously like the WASAPI one.
+pub struct PulseBackend {
+    mainloop: RefCell<Mainloop>,
+    context: RefCell<Context>,
+    // Sink-input changes delivered by the subscription, resolved by `session_events`
+    pending_events: Rc<RefCell<Vec<(SubscribeOperation, u32)>>>,
+}
+
+impl PulseBackend {
+    /// Connects to the default server (`$PULSE_SERVER` or the user's session daemon)
+    pub fn new() -> Result<Self> {
+        let mainloop = Mainloop::new().ok_or_else(|| pulse_error("Failed to create PulseAudio mainloop"))?;
+        let mut context = Context::new(&mainloop, env!("CARGO_PKG_NAME"))
+            .ok_or_else(|| pulse_error("Failed to create PulseAudio context"))?;
+        context.connect(None, ContextFlagSet::NOAUTOSPAWN, None)?;
+
+        let backend = Self {
+            mainloop: RefCell::new(mainloop),
+            context: RefCell::new(context),
+            pending_events: Rc::new(RefCell::new(Vec::new())),
+        };
+
+        // Wait for the connection to become ready
+        loop {
+            backend.iterate(true)?;
+            let state = backend.context.borrow().get_state();
+            match state {
+                ContextState::Ready => break,
+                ContextState::Failed | ContextState::Terminated => {
+                    return Err(pulse_error("Failed to connect to the PulseAudio server"));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(backend)
+    }
+
+    // Runs one mainloop iteration, returning the number of sources dispatched
+    fn iterate(&self, block: bool) -> Result<u32> {
+        match self.mainloop.borrow_mut().iterate(block) {
+            IterateResult::Success(dispatched) => Ok(dispatched),
+            IterateResult::Quit(_) => Err(pulse_error("PulseAudio mainloop quit")),
+            IterateResult::Err(e) => Err(e.into()),
+        }
+    }
+
+    // Runs the mainloop until an operation has finished
+    fn wait_for<T: ?Sized>(&self, operation: Operation<T>) -> Result<()> {
+        loop {
+            match operation.get_state() {
+                OperationState::Running => {
+                    self.iterate(true)?;
+                }
+                OperationState::Done => return Ok(()),
+                OperationState::Cancelled => return Err(pulse_error("PulseAudio operation was cancelled")),
+            }
+        }
+    }
+
+    // Waits for an operation that reports success through a callback
+    fn wait_for_success(&self, operation: Operation<dyn FnMut(bool)>, success: Rc<RefCell<bool>>) -> Result<()> {
+        self.wait_for(operation)?;
+        if *success.borrow() {
+            Ok(())
+        } else {
+            Err(self.context.borrow().errno().into())
+        }
+    }
+
+    fn sink_input_state(&self, session: &PulseSession) -> Result<StreamState> {
+        let state = Rc::new(RefCell::new(None));
+        let operation = {
+            let state = state.clone();
+            self.context.borrow().introspect().get_sink_input_info(session.index, move |result| {
+                if let ListResult::Item(info) = result {
+                    *state.borrow_mut() = Some(StreamState { volumes: info.volume, muted: info.mute, corked: info.corked });
+                }
+            })
+        };
+        self.wait_for(operation)?;
+
+        state.take().ok_or(Error::SessionExpired)
+    }
+
+    // The master volume is that of whichever sink is the default right now
+    fn default_sink_state(&self) -> Result<(ChannelVolumes, bool)> {
+        let state = Rc::new(RefCell::new(None));
+        let operation = {
+            let state = state.clone();
+            self.context.borrow().introspect().get_sink_info_by_name(DEFAULT_SINK, move |result| {
+                if let ListResult::Item(info) = result {
+                    *state.borrow_mut() = Some((info.volume, info.mute));
+                }
+            })
+        };
+        self.wait_for(operation)?;
+
+        state.take().ok_or_else(|| pulse_error("There is no default sink"))
+    }
+
+    fn sink_input(&self, index: u32) -> Result<Option<PulseSession>> {
+        let session = Rc::new(RefCell::new(None));
+        let operation = {
+            let session = session.clone();
+            self.context.borrow().introspect().get_sink_input_info(index, move |result| {
+                if let ListResult::Item(info) = result {
+                    *session.borrow_mut() = Some(PulseSession::from_info(info));
+                }
+            })
+        };
+        self.wait_for(operation)?;
+
+        Ok(session.take())
+    }
+}
+
+impl Drop for PulseBackend {
+    fn drop(&mut self) {
+        self.context.borrow_mut().disconnect();
+    }
+}
+
+impl AudioBackend for PulseBackend {
+    type Session = PulseSession;
+
+    fn sessions(&self) -> Result<Vec<PulseSession>> {
+        let sessions = Rc::new(RefCell::new(Vec::new()));
+        let operation = {
+            let sessions = sessions.clone();
+            self.context.borrow().introspect().get_sink_input_info_list(move |result| {
+                if let ListResult::Item(info) = result {
+                    sessions.borrow_mut().push(PulseSession::from_info(info));
+                }
+            })
+        };
+        self.wait_for(operation)?;
+
+        Ok(sessions.take())
+    }
+
+    fn session_pid(&self, session: &PulseSession) -> Result<u32> {
+        // Sink-inputs without a client process are treated like the system session
+        Ok(session.pid.unwrap_or(0))
+    }
+
+    fn session_path(&self, session: &PulseSession) -> Result<String> {
+        let exe = session.pid.and_then(|pid| std::fs::read_link(format!("/proc/{}/exe", pid)).ok());
+        client_path(exe, session.binary.as_deref(), session.pid.unwrap_or(0))
+    }
+
+    fn session_name(&self, session: &PulseSession) -> Result<Option<String>> {
+        Ok(session.name.clone())
+    }
+
+    fn volume(&self, session: &PulseSession) -> Result<f32> {
+        Ok(from_volumes(&self.sink_input_state(session)?.volumes))
+    }
+
+    fn set_volume(&self, session: &PulseSession, volume: f32) -> Result<()> {
+        // Keep the stream's channel count and set every channel to the same level
+        let mut volumes = self.sink_input_state(session)?.volumes;
+        set_volumes(&mut volumes, volume);
+
+        let success = Rc::new(RefCell::new(false));
+        let operation = {
+            let success = success.clone();
+            self.context.borrow().introspect().set_sink_input_volume(
+                session.index,
+                &volumes,
+                Some(Box::new(move |ok| *success.borrow_mut() = ok)),
+            )
+        };
+        self.wait_for_success(operation, success)
+    }
+
+    fn is_muted(&self, session: &PulseSession) -> Result<bool> {
+        Ok(self.sink_input_state(session)?.muted)
+    }
+
+    fn set_muted(&self, session: &PulseSession, muted: bool) -> Result<()> {
+        let success = Rc::new(RefCell::new(false));
+        let operation = {
+            let success = success.clone();
+            self.context.borrow().introspect().set_sink_input_mute(
+                session.index,
+                muted,
+                Some(Box::new(move |ok| *success.borrow_mut() = ok)),
+            )
+        };
+        self.wait_for_success(operation, success)
+    }
+
+    fn is_playing(&self, session: &PulseSession) -> Result<bool> {
+        // Pulse has no peak meter to poll, so an uncorked (unpaused) stream counts as playing
+        Ok(!self.sink_input_state(session)?.corked)
+    }
+
+    fn master_volume(&self) -> Result<f32> {
+        let (volumes, _) = self.default_sink_state()?;
+        Ok(from_volumes(&volumes))
+    }
+
+    fn set_master_volume(&self, volume: f32) -> Result<()> {
+        let (mut volumes, _) = self.default_sink_state()?;
+        set_volumes(&mut volumes, volume);
+
+        let success = Rc::new(RefCell::new(false));
+        let operation = {
+            let success = success.clone();
+            self.context.borrow().introspect().set_sink_volume_by_name(
+                DEFAULT_SINK,
+                &volumes,
+                Some(Box::new(move |ok| *success.borrow_mut() = ok)),
+            )
+        };
+        self.wait_for_success(operation, success)
+    }
+
+    fn is_master_muted(&self) -> Result<bool> {
+        let (_, muted) = self.default_sink_state()?;
+        Ok(muted)
+    }
+
+    fn set_master_muted(&self, muted: bool) -> Result<()> {
+        let success = Rc::new(RefCell::new(false));
+        let operation = {
+            let success = success.clone();
+            self.context.borrow().introspect().set_sink_mute_by_name(
+                DEFAULT_SINK,
+                muted,
+                Some(Box::new(move |ok| *success.borrow_mut() = ok)),
+            )
+        };
+        self.wait_for_success(operation, success)
+    }
+
+    fn watch_sessions(&self) -> Result<bool> {
+        let success = Rc::new(RefCell::new(false));
+        let operation = {
+            let mut context = self.context.borrow_mut();
+            let pending_events = self.pending_events.clone();
+            context.set_subscribe_callback(Some(Box::new(move |facility, operation, index| {
+                if facility == Some(Facility::SinkInput)
+                    && let Some(operation) = operation
+                {
+                    pending_events.borrow_mut().push((operation, index));
+                }
+            })));
+
+            let success = success.clone();
+            context.subscribe(InterestMaskSet::SINK_INPUT, move |ok| *success.borrow_mut() = ok)
+        };
+        self.wait_for_success(operation, success)?;
+
+        Ok(true)
+    }
+
+    fn session_events(&self) -> Result<Vec<SessionEvent<PulseSession>>> {
+        // Dispatch whatever the server has sent since the last call without blocking
+        while self.iterate(false)? > 0 {}
+
+        let mut events = Vec::new();
+        for (operation, index) in self.pending_events.take() {
+            match operation {
+                // Introspection can't wait inside the subscription callback, so details are fetched here
+                SubscribeOperation::New => {
+                    if let Some(session) = self.sink_input(index)? {
+                        events.push(SessionEvent::Added(session));
+                    }
+                }
+                SubscribeOperation::Removed => events.push(SessionEvent::Removed(PulseSession::from_index(index))),
+                SubscribeOperation::Changed => {}
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+fn from_volumes(volumes: &ChannelVolumes) -> f32 {
+    volumes.avg().0 as f32 / Volume::NORMAL.0 as f32
+}
+
+fn set_volumes(volumes: &mut ChannelVolumes, volume: f32) {
+    let channels = volumes.len();
+    volumes.set(channels, Volume((volume.max(0.0) * Volume::NORMAL.0 as f32).round() as u32));
+}
+
+// Picks a session's executable path from what `/proc/<pid>/exe` resolved to and
+// the binary name the client reported
+fn client_path(exe: Option<PathBuf>, binary: Option<&str>, pid: u32) -> Result<String> {
+    // Prefer the resolved executable so paths compare equal with the focused window's.
+    // A client in another PID namespace (e.g. Flatpak) reports its PID inside the
+    // sandbox, which can name an unrelated host process, so the names have to agree.
+    if let Some(exe) = exe
+        && binary.is_none_or(|binary| exe.file_name().is_some_and(|name| name == binary))
+    {
+        return Ok(exe.to_string_lossy().into_owned());
+    }
+
+    // Sessions that can't be traced to an executable are still found by PID
+    binary.map(str::to_string).ok_or(Error::ProcessAccessDenied { pid })
+}
+
+fn pulse_error(message: &str) -> Error {
+    Error::Backend(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests need a PulseAudio server with no real hardware, e.g.:
+    //!
+    //! ```sh
+    //! pulseaudio --daemonize --exit-idle-time=-1 --load=module-null-sink
+    //! cargo test --features pulse -- --ignored
+    //! ```
+
+    use super::*;
+    use crate::audio;
+    use std::process::{Child, Command};
+    use std::time::{Duration, Instant};
+
+    // Plays silence until killed
+    fn spawn_paplay() -> Child {
+        Command::new("paplay")
+            .args(["--raw", "/dev/zero"])
+            .spawn()
+            .expect("failed to spawn paplay")
+    }
+
+    fn wait_for_session(backend: &PulseBackend, pid: u32) -> PulseSession {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            for session in backend.sessions().unwrap() {
+                if backend.session_pid(&session).unwrap() == pid {
+                    return session;
+                }
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("paplay never created a sink-input");
+    }
+
+    #[test]
+    fn client_paths_prefer_an_executable_matching_the_binary() {
+        let exe = || Some(PathBuf::from("/usr/lib/firefox/firefox"));
+        assert_eq!(client_path(exe(), Some("firefox"), 20).unwrap(), "/usr/lib/firefox/firefox");
+        assert_eq!(client_path(exe(), None, 20).unwrap(), "/usr/lib/firefox/firefox");
+
+        // A sandboxed client's PID named some other host process
+        assert_eq!(client_path(Some(PathBuf::from("/usr/bin/bash")), Some("spotify"), 2).unwrap(), "spotify");
+        assert_eq!(client_path(None, Some("spotify"), 2).unwrap(), "spotify");
+
+        // Nothing to go on, so the session can only be found by PID
+        assert!(matches!(client_path(None, None, 20), Err(Error::ProcessAccessDenied { pid: 20 })));
+    }
+
+    #[test]
+    #[ignore = "requires a running PulseAudio server and paplay"]
+    fn finds_paplay_by_process_path() {
+        let backend = PulseBackend::new().unwrap();
+        let mut child = spawn_paplay();
+        let session = wait_for_session(&backend, child.id());
+
+        let path = backend.session_path(&session).unwrap();
+        assert!(path.ends_with("paplay"), "unexpected path {}", path);
+        assert_eq!(audio::get_session_by_process_path(&backend, &path).unwrap().index, session.index);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires a running PulseAudio server and paplay"]
+    fn sets_volume_and_mute() {
+        let backend = PulseBackend::new().unwrap();
+        let mut child = spawn_paplay();
+        let session = wait_for_session(&backend, child.id());
+
+        audio::set_session_volume(&backend, &session, 0.5).unwrap();
+        assert!((audio::get_session_volume(&backend, &session).unwrap() - 0.5).abs() < 0.01);
+
+        audio::mute_session(&backend, &session).unwrap();
+        assert!(backend.is_muted(&session).unwrap());
+        audio::toggle_session_mute(&backend, &session).unwrap();
+        assert!(!backend.is_muted(&session).unwrap());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires a running PulseAudio server and paplay"]
+    fn controls_the_default_sink_and_reports_playing_streams() {
+        let backend = PulseBackend::new().unwrap();
+        let mut child = spawn_paplay();
+        let session = wait_for_session(&backend, child.id());
+        assert!(backend.is_playing(&session).unwrap());
+
+        let (volume, muted) = (backend.master_volume().unwrap(), backend.is_master_muted().unwrap());
+        backend.set_master_volume(0.25).unwrap();
+        assert!((backend.master_volume().unwrap() - 0.25).abs() < 0.01);
+        assert_eq!(audio::toggle_master_mute(&backend).unwrap(), !muted);
+
+        backend.set_master_volume(volume).unwrap();
+        backend.set_master_muted(muted).unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    // Collects session events until one matches or five seconds pass
+    fn wait_for_event(backend: &PulseBackend, matches: impl Fn(&SessionEvent<PulseSession>) -> bool) -> SessionEvent<PulseSession> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(event) = backend.session_events().unwrap().into_iter().find(&matches) {
+                return event;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("expected session event never arrived");
+    }
+
+    #[test]
+    #[ignore = "requires a running PulseAudio server and paplay"]
+    fn reports_sink_inputs_as_they_come_and_go() {
+        let backend = PulseBackend::new().unwrap();
+        assert!(backend.watch_sessions().unwrap());
+
+        let mut child = spawn_paplay();
+        let pid = child.id();
+        let added = wait_for_event(&backend, |event| {
+            matches!(event, SessionEvent::Added(session) if session.pid == Some(pid))
+        });
+        let SessionEvent::Added(session) = added else { unreachable!() };
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        wait_for_event(&backend, |event| *event == SessionEvent::Removed(session.clone()));
+    }
+}
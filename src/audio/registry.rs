@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use crate::audio::{AudioBackend, SessionEvent};
+use crate::error::{Error, Result};
+
+/// Sessions indexed by the pid and executable path of their owning process.
+///
+/// The registry is filled by one full scan and then kept up to date from the
+/// backend's session events, so looking up the focused application doesn't
+/// enumerate every session or resolve every process path. Backends that can't
+/// watch for changes are rescanned on every `sync` instead.
+pub struct SessionRegistry<S> {
+    entries: Vec<Entry<S>>,
+    by_pid: HashMap<u32, Vec<S>>,
+    by_path: HashMap<String, Vec<S>>,
+    by_file_name: HashMap<String, Vec<S>>,
+    watching: bool,
+    stale: bool,
+}
+
+struct Entry<S> {
+    session: S,
+    pid: u32,
+    // None when the process can't be opened, e.g. because it is elevated
+    path: Option<String>,
+    name: Option<String>,
+}
+
+/// A session as the registry knows it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisteredSession<'a, S> {
+    pub session: &'a S,
+    pub pid: u32,
+    pub path: Option<&'a str>,
+    /// The application name the session reports, if the backend has one
+    pub name: Option<&'a str>,
+}
+
+impl<S: Clone + PartialEq> SessionRegistry<S> {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            by_pid: HashMap::new(),
+            by_path: HashMap::new(),
+            by_file_name: HashMap::new(),
+            watching: false,
+            stale: true,
+        }
+    }
+
+    /// Brings the registry up to date with the backend
+    pub fn sync<B: AudioBackend<Session = S>>(&mut self, backend: &B) -> Result<()> {
+        if self.stale || !self.watching {
+            // Start watching before the scan so sessions created during it aren't missed
+            if !self.watching {
+                // Without notifications every sync rescans, which is slower but still correct
+                self.watching = backend.watch_sessions().unwrap_or_else(|e| {
+                    println!("Error watching audio sessions: {}", e);
+                    false
+                });
+            }
+            self.rescan(backend)?;
+            self.stale = false;
+            return Ok(());
+        }
+
+        for event in backend.session_events()? {
+            let applied = match event {
+                SessionEvent::Added(session) => self.add(backend, session),
+                SessionEvent::Removed(session) => {
+                    self.remove(&session);
+                    Ok(())
+                }
+            };
+
+            // The remaining events are lost, so start over on the next sync
+            if let Err(e) = applied {
+                self.stale = true;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forgets a session the backend reported as expired
+    pub fn invalidate(&mut self, session: &S) {
+        self.remove(session);
+    }
+
+    /// Rescans on the next `sync`, for when some session of a group expired but it isn't known which
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    /// Sessions owned by the process, in the order they were registered
+    pub fn sessions_for_pid(&self, pid: u32) -> &[S] {
+        self.by_pid.get(&pid).map_or(&[], Vec::as_slice)
+    }
+
+    /// Sessions owned by processes running the executable at `path`
+    pub fn sessions_for_path(&self, path: &str) -> &[S] {
+        self.by_path.get(path).map_or(&[], Vec::as_slice)
+    }
+
+    /// Sessions whose executable has the same file name as `path`, ignoring case
+    pub fn sessions_for_file_name(&self, path: &str) -> &[S] {
+        self.by_file_name.get(&file_name(path)).map_or(&[], Vec::as_slice)
+    }
+
+    /// Gets the first session owned by the executable at `path`
+    pub fn find_by_path(&self, path: &str) -> Result<S> {
+        self.sessions_for_path(path)
+            .first()
+            .cloned()
+            .ok_or_else(|| Error::SessionNotFound { path: path.to_string() })
+    }
+
+    /// Every registered session, in the order they were registered
+    pub fn iter(&self) -> impl Iterator<Item = RegisteredSession<'_, S>> {
+        self.entries.iter().map(|entry| RegisteredSession {
+            session: &entry.session,
+            pid: entry.pid,
+            path: entry.path.as_deref(),
+            name: entry.name.as_deref(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn rescan<B: AudioBackend<Session = S>>(&mut self, backend: &B) -> Result<()> {
+        self.entries.clear();
+        self.by_pid.clear();
+        self.by_path.clear();
+        self.by_file_name.clear();
+
+        for session in backend.sessions()? {
+            self.add(backend, session)?;
+        }
+
+        Ok(())
+    }
+
+    fn add<B: AudioBackend<Session = S>>(&mut self, backend: &B, session: S) -> Result<()> {
+        // Sessions seen by the initial scan can be reported again as added
+        if self.entries.iter().any(|entry| entry.session == session) {
+            return Ok(());
+        }
+
+        let pid = match backend.session_pid(&session) {
+            Ok(pid) => pid,
+            Err(Error::SessionExpired) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if pid == 0 {
+            // Skip the system session
+            return Ok(());
+        }
+
+        let path = match backend.session_path(&session) {
+            Ok(path) => Some(path),
+            Err(Error::ProcessAccessDenied { .. }) => None,
+            Err(Error::SessionExpired) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        // The name is only a hint for alias rules, so not having one is fine
+        let name = backend.session_name(&session).ok().flatten();
+
+        self.by_pid.entry(pid).or_default().push(session.clone());
+        if let Some(path) = &path {
+            self.by_path.entry(path.clone()).or_default().push(session.clone());
+            self.by_file_name.entry(file_name(path)).or_default().push(session.clone());
+        }
+        self.entries.push(Entry { session, pid, path, name });
+
+        Ok(())
+    }
+
+    fn remove(&mut self, session: &S) {
+        let Some(index) = self.entries.iter().position(|entry| entry.session == *session) else {
+            return;
+        };
+        let entry = self.entries.remove(index);
+
+        remove_from(&mut self.by_pid, &entry.pid, session);
+        if let Some(path) = &entry.path {
+            remove_from(&mut self.by_path, path, session);
+            remove_from(&mut self.by_file_name, &file_name(path), session);
+        }
+    }
+}
+
+impl<S: Clone + PartialEq> Default for SessionRegistry<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Session paths can be Windows paths, Linux paths or bare binary names
+fn file_name(path: &str) -> String {
+    path.rsplit(['/', '\\']).next().unwrap_or(path).to_lowercase()
+}
+
+fn remove_from<K, Q, S>(index: &mut HashMap<K, Vec<S>>, key: &Q, session: &S)
+where
+    K: std::borrow::Borrow<Q> + std::hash::Hash + Eq,
+    Q: std::hash::Hash + Eq + ?Sized,
+    S: PartialEq,
+{
+    if let Some(sessions) = index.get_mut(key) {
+        sessions.retain(|s| s != session);
+        if sessions.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::MockAudioBackend;
+
+    #[test]
+    fn indexes_existing_sessions_by_pid_and_path() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(0, "");
+        let spotify = backend.add_session(42, "C:\\Spotify\\Spotify.exe");
+        let elevated = backend.add_session(5, "C:\\Admin\\Tool.exe");
+        backend.deny_access(elevated);
+
+        let mut registry = SessionRegistry::new();
+        registry.sync(&backend).unwrap();
+
+        assert_eq!(registry.len(), 2, "the system session should be skipped");
+        assert_eq!(registry.sessions_for_pid(42), &[spotify]);
+        assert_eq!(registry.find_by_path("C:\\Spotify\\Spotify.exe").unwrap(), spotify);
+        assert_eq!(registry.sessions_for_pid(5), &[elevated]);
+        assert!(registry.sessions_for_path("C:\\Admin\\Tool.exe").is_empty());
+        assert_eq!(registry.sessions_for_file_name("D:\\Portable\\spotify.EXE"), &[spotify]);
+        assert!(matches!(registry.find_by_path("C:\\Other.exe"), Err(Error::SessionNotFound { .. })));
+    }
+
+    #[test]
+    fn follows_added_and_removed_events_without_rescanning() {
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+
+        let mut registry = SessionRegistry::new();
+        registry.sync(&backend).unwrap();
+        assert_eq!(backend.scan_count(), 1);
+
+        let firefox = backend.add_session(30, "/usr/lib/firefox/firefox");
+        registry.sync(&backend).unwrap();
+        assert_eq!(registry.find_by_path("/usr/lib/firefox/firefox").unwrap(), firefox);
+
+        backend.remove_session(mpv);
+        registry.sync(&backend).unwrap();
+        assert!(registry.sessions_for_pid(20).is_empty());
+        assert!(registry.sessions_for_path("/usr/bin/mpv").is_empty());
+        assert!(registry.sessions_for_file_name("mpv").is_empty());
+        assert_eq!(registry.len(), 1);
+
+        assert_eq!(backend.scan_count(), 1, "events should be applied without enumerating sessions again");
+    }
+
+    #[test]
+    fn ignores_duplicate_and_expired_additions() {
+        let backend = MockAudioBackend::new();
+        let mut registry = SessionRegistry::new();
+        registry.sync(&backend).unwrap();
+
+        // Added and gone again before the registry saw it
+        let brief = backend.add_session(20, "/usr/bin/mpv");
+        backend.remove_session(brief);
+        let mpv = backend.add_session(21, "/usr/bin/mpv");
+        backend.emit_added(mpv);
+        registry.sync(&backend).unwrap();
+
+        assert_eq!(registry.sessions_for_path("/usr/bin/mpv"), &[mpv]);
+    }
+
+    #[test]
+    fn rescans_backends_that_cannot_watch() {
+        let backend = MockAudioBackend::new();
+        backend.disable_notifications();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+
+        let mut registry = SessionRegistry::new();
+        registry.sync(&backend).unwrap();
+        backend.remove_session(mpv);
+        let vlc = backend.add_session(30, "/usr/bin/vlc");
+        registry.sync(&backend).unwrap();
+
+        assert_eq!(backend.scan_count(), 2);
+        assert!(registry.sessions_for_path("/usr/bin/mpv").is_empty());
+        assert_eq!(registry.find_by_path("/usr/bin/vlc").unwrap(), vlc);
+    }
+
+    #[test]
+    fn rescans_when_watching_fails() {
+        let backend = MockAudioBackend::new();
+        backend.fail_notifications();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+
+        let mut registry = SessionRegistry::new();
+        registry.sync(&backend).unwrap();
+        assert_eq!(registry.find_by_path("/usr/bin/mpv").unwrap(), mpv);
+
+        let vlc = backend.add_session(30, "/usr/bin/vlc");
+        registry.sync(&backend).unwrap();
+        assert_eq!(backend.scan_count(), 2);
+        assert_eq!(registry.find_by_path("/usr/bin/vlc").unwrap(), vlc);
+    }
+
+    #[test]
+    fn invalidated_sessions_are_forgotten() {
+        let backend = MockAudioBackend::new();
+        let first = backend.add_session(20, "/usr/bin/mpv");
+        let second = backend.add_session(21, "/usr/bin/mpv");
+
+        let mut registry = SessionRegistry::new();
+        registry.sync(&backend).unwrap();
+        registry.invalidate(&first);
+
+        assert_eq!(registry.find_by_path("/usr/bin/mpv").unwrap(), second);
+        assert!(registry.sessions_for_pid(20).is_empty());
+    }
+
+    #[test]
+    fn stale_registries_rescan_on_next_sync() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(20, "/usr/bin/mpv");
+
+        let mut registry = SessionRegistry::new();
+        registry.sync(&backend).unwrap();
+        registry.mark_stale();
+        registry.sync(&backend).unwrap();
+
+        assert_eq!(backend.scan_count(), 2);
+        assert_eq!(registry.len(), 1);
+    }
+}
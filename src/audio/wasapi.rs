@@ -0,0 +1,395 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use windows::Win32::Foundation::BOOL;
+use windows::Win32::Media::Audio::*;
+use windows::Win32::Media::Audio::Endpoints::{IAudioEndpointVolume, IAudioMeterInformation};
+use windows::Win32::System::Com::CLSCTX_ALL;
+use windows::Win32::UI::Shell::PropertiesSystem::PROPERTYKEY;
+use windows::core::{implement, ComInterface, GUID, PCWSTR};
+use crate::audio::{AudioBackend, SessionEvent};
+use crate::error::Result;
+use crate::focus::win32::get_process_path;
+
+// Events queued by COM callbacks, which run on threads owned by the audio service
+type EventQueue = Arc<Mutex<Vec<SessionEvent<IAudioSessionControl2>>>>;
+
+/// Audio backend for the Windows Audio Session API.
+///
+/// This is a long-lived context: it initializes COM for the calling thread
+/// and keeps the device enumerator and default render endpoint for as long as
+/// it lives. Create one per thread and drop it on that same thread. When the
+/// default endpoint changes, `device_changed` says so and the backend has to
+/// be replaced.
+pub struct WasapiBackend {
+    session_manager: IAudioSessionManager2,
+    endpoint_volume: IAudioEndpointVolume,
+    events: EventQueue,
+    // Set by `device_notifier` when another endpoint becomes the default
+    device_changed: Arc<AtomicBool>,
+    device_notifier: IMMNotificationClient,
+    // Registered by `watch_sessions` and unregistered on drop
+    notifier: RefCell<Option<IAudioSessionNotification>>,
+    watchers: RefCell<Vec<(IAudioSessionControl2, IAudioSessionEvents)>>,
+    _device: IMMDevice,
+    _enumerator: IMMDeviceEnumerator,
+    // Declared last so COM is uninitialized after the interfaces above are released
+    _com: ComGuard,
+}
+
+// Balances a successful CoInitializeEx on the current thread
+struct ComGuard;
+
+impl Drop for ComGuard {
+    fn drop(&mut self) {
+        unsafe {
+            windows::Win32::System::Com::CoUninitialize();
+        }
+    }
+}
+
+impl WasapiBackend {
+    pub fn new() -> Result<Self> {
+        unsafe {
+            // Initialize COM library; session notifications are only delivered to the multithreaded apartment
+            windows::Win32::System::Com::CoInitializeEx(None, windows::Win32::System::Com::COINIT_MULTITHREADED)?;
+            let com = ComGuard;
+
+            // Create a multimedia device enumerator
+            let enumerator: IMMDeviceEnumerator = windows::Win32::System::Com::CoCreateInstance(&MMDeviceEnumerator, None, windows::Win32::System::Com::CLSCTX_ALL)?;
+
+            // Get the default audio endpoint
+            let device = enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?;
+
+            // Activate the audio session manager
+            let session_manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, Some(std::ptr::null_mut()))?;
+
+            // And the endpoint's own volume control, for the master volume
+            let endpoint_volume: IAudioEndpointVolume = device.Activate(CLSCTX_ALL, Some(std::ptr::null_mut()))?;
+
+            // Session controls are free-threaded, windows-rs just doesn't mark Win32 interfaces Send
+            #[allow(clippy::arc_with_non_send_sync)]
+            let events: EventQueue = Arc::new(Mutex::new(Vec::new()));
+
+            // Sessions and the endpoint volume belong to this device, so a new default needs a new backend
+            let device_changed = Arc::new(AtomicBool::new(false));
+            let device_notifier: IMMNotificationClient = DeviceNotifier { changed: device_changed.clone() }.into();
+            enumerator.RegisterEndpointNotificationCallback(&device_notifier)?;
+
+            Ok(Self {
+                session_manager,
+                endpoint_volume,
+                events,
+                device_changed,
+                device_notifier,
+                notifier: RefCell::new(None),
+                watchers: RefCell::new(Vec::new()),
+                _device: device,
+                _enumerator: enumerator,
+                _com: com,
+            })
+        }
+    }
+
+    // Asks the session to report when it expires
+    fn watch_session(&self, session: &IAudioSessionControl2) -> Result<()> {
+        if self.watchers.borrow().iter().any(|(watched, _)| watched == session) {
+            return Ok(());
+        }
+
+        let watcher: IAudioSessionEvents = SessionWatcher {
+            session: session.clone(),
+            events: self.events.clone(),
+        }.into();
+        unsafe {
+            session.RegisterAudioSessionNotification(&watcher)?;
+        }
+        self.watchers.borrow_mut().push((session.clone(), watcher));
+
+        Ok(())
+    }
+
+    // A session that refuses to be watched, e.g. because it is already expiring,
+    // only misses its expiry notification, which lookups recover from
+    fn watch_session_or_log(&self, session: &IAudioSessionControl2) {
+        if let Err(e) = self.watch_session(session) {
+            println!("Error watching audio session: {}", e);
+        }
+    }
+
+    // Unregistering from inside a callback isn't allowed, so expired sessions are released here
+    fn unwatch_session(&self, session: &IAudioSessionControl2) {
+        let mut watchers = self.watchers.borrow_mut();
+        if let Some(index) = watchers.iter().position(|(watched, _)| watched == session) {
+            let (session, watcher) = watchers.swap_remove(index);
+            unsafe {
+                let _ = session.UnregisterAudioSessionNotification(&watcher);
+            }
+        }
+    }
+}
+
+impl Drop for WasapiBackend {
+    fn drop(&mut self) {
+        // The watchers and the sessions hold references to each other until unregistered
+        for (session, watcher) in self.watchers.take() {
+            unsafe {
+                let _ = session.UnregisterAudioSessionNotification(&watcher);
+            }
+        }
+        if let Some(notifier) = self.notifier.take() {
+            unsafe {
+                let _ = self.session_manager.UnregisterSessionNotification(&notifier);
+            }
+        }
+        unsafe {
+            let _ = self._enumerator.UnregisterEndpointNotificationCallback(&self.device_notifier);
+        }
+    }
+}
+
+impl AudioBackend for WasapiBackend {
+    type Session = IAudioSessionControl2;
+
+    fn sessions(&self) -> Result<Vec<IAudioSessionControl2>> {
+        unsafe {
+            // Get the audio session enumerator
+            let session_enumerator = self.session_manager.GetSessionEnumerator()?;
+
+            // Get the number of audio sessions
+            let count = session_enumerator.GetCount()?;
+
+            let mut sessions = Vec::with_capacity(count as usize);
+            for i in 0..count {
+                // Get the basic session control
+                let session_control = session_enumerator.GetSession(i)?;
+
+                // Use the cast method to convert to IAudioSessionControl2
+                let session_control2: IAudioSessionControl2 = session_control.cast()?;
+                sessions.push(session_control2);
+            }
+
+            Ok(sessions)
+        }
+    }
+
+    fn session_pid(&self, session: &IAudioSessionControl2) -> Result<u32> {
+        unsafe {
+            Ok(session.GetProcessId()?)
+        }
+    }
+
+    fn session_path(&self, session: &IAudioSessionControl2) -> Result<String> {
+        let pid = self.session_pid(session)?;
+        get_process_path(pid)
+    }
+
+    fn volume(&self, session: &IAudioSessionControl2) -> Result<f32> {
+        unsafe {
+            // Get the simple audio volume interface from the session control
+            let simple_audio_volume: ISimpleAudioVolume = session.cast()?;
+
+            // Get the current master volume level directly from the method return
+            let volume_level = simple_audio_volume.GetMasterVolume()?;
+
+            Ok(volume_level)
+        }
+    }
+
+    fn set_volume(&self, session: &IAudioSessionControl2, volume: f32) -> Result<()> {
+        unsafe {
+            // Get the simple audio volume interface from the session control
+            let simple_audio_volume: ISimpleAudioVolume = session.cast()?;
+
+            // Set the master volume level
+            simple_audio_volume.SetMasterVolume(volume, std::ptr::null())?;
+
+            Ok(())
+        }
+    }
+
+    fn is_muted(&self, session: &IAudioSessionControl2) -> Result<bool> {
+        unsafe {
+            // Get the simple audio volume interface from the session control
+            let simple_audio_volume: ISimpleAudioVolume = session.cast()?;
+
+            Ok(simple_audio_volume.GetMute()?.as_bool())
+        }
+    }
+
+    fn set_muted(&self, session: &IAudioSessionControl2, muted: bool) -> Result<()> {
+        unsafe {
+            // Get the simple audio volume interface from the session control
+            let simple_audio_volume: ISimpleAudioVolume = session.cast()?;
+
+            // Mute or unmute the session
+            simple_audio_volume.SetMute(muted, std::ptr::null())?;
+
+            Ok(())
+        }
+    }
+
+    fn is_playing(&self, session: &IAudioSessionControl2) -> Result<bool> {
+        unsafe {
+            // The peak meter reads zero for sessions that are silent, even when they are active
+            let meter: IAudioMeterInformation = session.cast()?;
+            Ok(meter.GetPeakValue()? > 0.0)
+        }
+    }
+
+    fn master_volume(&self) -> Result<f32> {
+        unsafe {
+            Ok(self.endpoint_volume.GetMasterVolumeLevelScalar()?)
+        }
+    }
+
+    fn set_master_volume(&self, volume: f32) -> Result<()> {
+        unsafe {
+            self.endpoint_volume.SetMasterVolumeLevelScalar(volume, std::ptr::null())?;
+            Ok(())
+        }
+    }
+
+    fn is_master_muted(&self) -> Result<bool> {
+        unsafe {
+            Ok(self.endpoint_volume.GetMute()?.as_bool())
+        }
+    }
+
+    fn set_master_muted(&self, muted: bool) -> Result<()> {
+        unsafe {
+            self.endpoint_volume.SetMute(muted, std::ptr::null())?;
+            Ok(())
+        }
+    }
+
+    fn watch_sessions(&self) -> Result<bool> {
+        if self.notifier.borrow().is_some() {
+            return Ok(true);
+        }
+
+        let notifier: IAudioSessionNotification = SessionNotifier { events: self.events.clone() }.into();
+        unsafe {
+            self.session_manager.RegisterSessionNotification(&notifier)?;
+        }
+        *self.notifier.borrow_mut() = Some(notifier);
+
+        // Enumerating also tells the session manager to start sending notifications
+        for session in self.sessions()? {
+            self.watch_session_or_log(&session);
+        }
+
+        Ok(true)
+    }
+
+    fn session_events(&self) -> Result<Vec<SessionEvent<IAudioSessionControl2>>> {
+        let events = std::mem::take(&mut *self.events.lock().unwrap());
+        for event in &events {
+            match event {
+                SessionEvent::Added(session) => self.watch_session_or_log(session),
+                SessionEvent::Removed(session) => self.unwatch_session(session),
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn device_changed(&self) -> bool {
+        self.device_changed.load(Ordering::SeqCst)
+    }
+}
+
+// Reports the default render endpoint changing, such as when a headset is plugged in
+#[implement(IMMNotificationClient)]
+struct DeviceNotifier {
+    changed: Arc<AtomicBool>,
+}
+
+impl IMMNotificationClient_Impl for DeviceNotifier {
+    fn OnDeviceStateChanged(&self, _pwstrdeviceid: &PCWSTR, _dwnewstate: u32) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, _pwstrdeviceid: &PCWSTR) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, _pwstrdeviceid: &PCWSTR) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnDefaultDeviceChanged(&self, flow: EDataFlow, role: ERole, _pwstrdefaultdeviceid: &PCWSTR) -> windows::core::Result<()> {
+        // The backend follows the console render endpoint; removing the default device also lands here
+        if flow == eRender && role == eConsole {
+            self.changed.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(&self, _pwstrdeviceid: &PCWSTR, _key: &PROPERTYKEY) -> windows::core::Result<()> {
+        Ok(())
+    }
+}
+
+// Reports sessions created on the default endpoint
+#[implement(IAudioSessionNotification)]
+struct SessionNotifier {
+    events: EventQueue,
+}
+
+impl IAudioSessionNotification_Impl for SessionNotifier {
+    fn OnSessionCreated(&self, newsession: Option<&IAudioSessionControl>) -> windows::core::Result<()> {
+        if let Some(session) = newsession {
+            let session: IAudioSessionControl2 = session.cast()?;
+            self.events.lock().unwrap().push(SessionEvent::Added(session));
+        }
+        Ok(())
+    }
+}
+
+// Reports when one session expires or is disconnected from its device
+#[implement(IAudioSessionEvents)]
+struct SessionWatcher {
+    session: IAudioSessionControl2,
+    events: EventQueue,
+}
+
+impl SessionWatcher {
+    fn removed(&self) {
+        self.events.lock().unwrap().push(SessionEvent::Removed(self.session.clone()));
+    }
+}
+
+impl IAudioSessionEvents_Impl for SessionWatcher {
+    fn OnDisplayNameChanged(&self, _newdisplayname: &PCWSTR, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnIconPathChanged(&self, _newiconpath: &PCWSTR, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnSimpleVolumeChanged(&self, _newvolume: f32, _newmute: BOOL, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnChannelVolumeChanged(&self, _channelcount: u32, _newchannelvolumearray: *const f32, _changedchannel: u32, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnGroupingParamChanged(&self, _newgroupingparam: *const GUID, _eventcontext: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnStateChanged(&self, newstate: AudioSessionState) -> windows::core::Result<()> {
+        if newstate == AudioSessionStateExpired {
+            self.removed();
+        }
+        Ok(())
+    }
+
+    fn OnSessionDisconnected(&self, _disconnectreason: AudioSessionDisconnectReason) -> windows::core::Result<()> {
+        self.removed();
+        Ok(())
+    }
+}
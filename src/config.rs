@@ -0,0 +1,216 @@
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::acceleration::AccelerationConfig;
+use crate::error::Result;
+use crate::keyboard::HotkeyConfig;
+use crate::matcher::MatcherConfig;
+use crate::target::{FallbackConfig, TargetingConfig};
+
+/// Settings read from `config.toml`. Anything left out keeps its default.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub matching: MatcherConfig,
+    pub fallback: FallbackConfig,
+    pub targeting: TargetingConfig,
+    pub hotkeys: HotkeyConfig,
+    pub acceleration: AccelerationConfig,
+}
+
+impl Config {
+    /// Reads the config file from `default_path`, or gives the defaults if there is none
+    pub fn load() -> Result<Self> {
+        match default_path() {
+            Some(path) if path.exists() => Self::load_from(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let config: Config = toml::from_str(text)?;
+        for rule in &config.matching.aliases {
+            rule.validate()?;
+        }
+        for app in &config.fallback.apps {
+            app.validate()?;
+        }
+        config.targeting.validate()?;
+        config.acceleration.validate()?;
+        Ok(config)
+    }
+}
+
+/// Where the config file lives: `%APPDATA%\focused-window-volume\config.toml`
+/// on Windows and `$XDG_CONFIG_HOME/focused-window-volume/config.toml` elsewhere
+pub fn default_path() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.map(|base| base.join("focused-window-volume").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::{CurveConfig, Reversal, Step, Stepped, Table};
+    use crate::error::Error;
+    use crate::matcher::alias::Pattern;
+    use crate::keyboard::Modifier;
+    use crate::scale::VolumeScale;
+    use crate::target::{Fallback, KeyScope, WindowSource};
+
+    #[test]
+    fn parses_matching_settings_and_alias_rules() {
+        let config = Config::parse(r#"
+            [matching]
+            max_process_depth = 5
+
+            [[matching.aliases]]
+            when_executable = '*\Discord.exe'
+            target_parent = "Update.exe"
+
+            [[matching.aliases]]
+            when_class = 're:^steam_app_\d+$'
+            target_name = "*"
+        "#).unwrap();
+
+        assert_eq!(config.matching.max_process_depth, 5);
+        assert_eq!(config.matching.aliases.len(), 2);
+        assert_eq!(config.matching.aliases[0].when_executable, Some(Pattern::new("*\\Discord.exe").unwrap()));
+        assert_eq!(config.matching.aliases[1].when_class.as_ref().map(Pattern::as_str), Some("re:^steam_app_\\d+$"));
+    }
+
+    #[test]
+    fn parses_global_and_per_application_fallbacks() {
+        let config = Config::parse(r#"
+            [fallback]
+            default = "master"
+
+            [[fallback.apps]]
+            when_executable = '*\Code.exe'
+            fallback = "last-adjusted"
+        "#).unwrap();
+
+        assert_eq!(config.fallback.default, Fallback::Master);
+        assert_eq!(config.fallback.apps[0].fallback, Fallback::LastAdjusted);
+        assert_eq!(Config::default().fallback.default, Fallback::PassThrough);
+    }
+
+    #[test]
+    fn parses_targeting_settings() {
+        let config = Config::parse(r#"
+            [targeting]
+            window = "cursor"
+            switch_modifier = "alt"
+
+            [targeting.chords]
+            ctrl = "master"
+        "#).unwrap();
+
+        assert_eq!(config.targeting.window, WindowSource::Cursor);
+        assert_eq!(config.targeting.switch_modifier, Some(Modifier::Alt));
+        assert_eq!(config.targeting.chords.ctrl, KeyScope::Master);
+        assert_eq!(config.targeting.chords.shift, KeyScope::Master);
+        assert_eq!(config.targeting.chords.alt, KeyScope::Focused);
+        assert_eq!(Config::default().targeting.window, WindowSource::Focus);
+    }
+
+    #[test]
+    fn parses_hotkeys_and_turns_them_off() {
+        let config = Config::parse(r#"
+            [hotkeys]
+            pin = "none"
+            solo = "ctrl+shift+s"
+            mute_hold_solo_ms = 600
+        "#).unwrap();
+
+        assert_eq!(config.hotkeys.pin, None);
+        assert_eq!(config.hotkeys.solo.map(|hotkey| hotkey.to_string()), Some("Ctrl+Shift+S".to_string()));
+        assert_eq!(config.hotkeys.mute_hold_solo_ms, Some(600));
+        assert_eq!(Config::default().hotkeys.mute_hold_solo_ms, None);
+    }
+
+    #[test]
+    fn parses_acceleration_curves() {
+        let config = Config::parse(r#"
+            [acceleration]
+            base_increment = 0.02
+            scale = { decibels = 50.0 }
+            history = 3
+            on_reversal = { damp = 0.5 }
+            idle_reset_ms = 2000
+            ramp_per_sec = 0.4
+
+            [acceleration.curve]
+            type = "table"
+            points = [[0, 8.0], [200, 3.0], [600, 1.0]]
+        "#).unwrap();
+
+        assert_eq!(config.acceleration.base_increment, 0.02);
+        assert_eq!(config.acceleration.history, 3);
+        assert_eq!(config.acceleration.scale, VolumeScale::Decibels(50.0));
+        assert_eq!(config.acceleration.on_reversal, Reversal::Damp(0.5));
+        assert_eq!(config.acceleration.idle_reset_ms, 2000);
+        assert_eq!(config.acceleration.ramp_per_sec, Some(0.4));
+        assert_eq!(config.acceleration.ramp_delay_ms, 300);
+        assert_eq!(config.acceleration.curve, CurveConfig::Table(Table { points: vec![(0, 8.0), (200, 3.0), (600, 1.0)] }));
+
+        let config = Config::parse(r#"
+            [acceleration.curve]
+            type = "stepped"
+            steps = [{ below_ms = 100, factor = 6.0 }, { below_ms = 250, factor = 2.5 }]
+            otherwise = 1.0
+        "#).unwrap();
+        assert_eq!(config.acceleration.history, 5);
+        assert_eq!(config.acceleration.scale, VolumeScale::Linear);
+        assert_eq!(config.acceleration.on_reversal, Reversal::Reset);
+        assert_eq!(config.acceleration.ramp_per_sec, None);
+        assert_eq!(config.acceleration.curve, CurveConfig::Stepped(Stepped {
+            steps: vec![Step { below_ms: 100, factor: 6.0 }, Step { below_ms: 250, factor: 2.5 }],
+            otherwise: 1.0,
+        }));
+    }
+
+    #[test]
+    fn missing_settings_keep_their_defaults() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert_eq!(Config::parse("[matching]\n").unwrap().matching.max_process_depth, 3);
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let cases = [
+            "[[matching.aliases]]\nwhen_class = 're:('\ntarget_name = '*'",
+            "[[matching.aliases]]\ntarget_name = '*'",
+            "[[matching.aliases]]\nwhen_class = '*'\ntarget_pid = 3",
+            "[matching]\nmax_depth = 3",
+            "[fallback]\ndefault = 'nothing'",
+            "[[fallback.apps]]\nfallback = 'master'",
+            "[targeting]\nswitch_modifier = 'win'",
+            "[targeting.chords]\nshift = 'everything'",
+            "[targeting]\nswitch_modifier = 'shift'",
+            "[targeting]\nswitch_modifier = 'alt'\n[targeting.chords]\nalt = 'others'",
+            "[hotkeys]\nsolo = 's'",
+            "[acceleration]\nhistory = 0",
+            "[acceleration]\non_reversal = { damp = 0.0 }",
+            "[acceleration]\non_reversal = 'keep'",
+            "[acceleration]\nramp_per_sec = -0.5",
+            "[acceleration]\nscale = { decibels = 0.0 }",
+            "[acceleration]\nscale = 'log'",
+            "[acceleration.curve]\ntype = 'cubic'",
+            "[acceleration.curve]\ntype = 'linear'\nmin = 1.0\nmax = 4.0\nfast_ms = 400\nslow_ms = 100",
+        ];
+
+        for text in cases {
+            assert!(matches!(Config::parse(text), Err(Error::InvalidConfig(_))), "{:?}", text);
+        }
+    }
+}
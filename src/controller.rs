@@ -0,0 +1,518 @@
+use std::time::{Duration, Instant};
+use crate::acceleration::AccelerationConfig;
+use crate::audio::{self, AudioBackend};
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::focus::FocusProvider;
+use crate::keyboard::{HotkeyConfig, Modifiers};
+use crate::target::{Target, TargetSelector, FOCUS_POLL_INTERVAL};
+use crate::volume::{self, Direction, KeyOutcome, VolumeKey, VolumeKeyState};
+use crate::worker::Command;
+
+/// How a press of the mute key ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutePress {
+    /// Let go quickly, so it mutes as usual
+    Tap(Modifiers),
+    /// Held for at least the threshold, so it toggles solo
+    Hold,
+}
+
+/// Tells a tap of the mute key from a long press, timed from key down to key up
+pub struct MuteHold {
+    clock: Box<dyn Clock>,
+    threshold: Duration,
+    // When the key went down and what was held with it
+    pressed: Option<(Instant, Modifiers)>,
+}
+
+impl MuteHold {
+    pub fn new(clock: impl Clock + 'static, threshold: Duration) -> Self {
+        Self {
+            clock: Box::new(clock),
+            threshold,
+            pressed: None,
+        }
+    }
+
+    /// Notes the key going down; autorepeat while it is held doesn't restart the timing
+    pub fn press(&mut self, modifiers: Modifiers) {
+        if self.pressed.is_none() {
+            self.pressed = Some((self.clock.now(), modifiers));
+        }
+    }
+
+    /// Notes the key coming up, or gives None for a release without a press
+    pub fn release(&mut self) -> Option<MutePress> {
+        let (pressed_at, modifiers) = self.pressed.take()?;
+        if self.clock.now().duration_since(pressed_at) >= self.threshold {
+            Some(MutePress::Hold)
+        } else {
+            Some(MutePress::Tap(modifiers))
+        }
+    }
+}
+
+/// How often a held volume key moves the volume while it ramps
+pub const RAMP_INTERVAL: Duration = Duration::from_millis(30);
+
+/// Whether a key down starts a hold or is autorepeat of the one already held
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RampPress {
+    Fresh,
+    Repeat,
+}
+
+/// Turns a held volume key into a steady ramp, paced by the clock from key
+/// down to key up rather than by the keyboard's autorepeat rate
+pub struct HoldRamp {
+    clock: Box<dyn Clock>,
+    // Volume out of 1.0 per second
+    rate: f32,
+    delay: Duration,
+    held: Option<Hold>,
+}
+
+#[derive(Clone, Copy)]
+struct Hold {
+    direction: Direction,
+    modifiers: Modifiers,
+    // How far the ramp has got: when it last moved the volume, or when it is due to start
+    ramped_until: Instant,
+}
+
+impl HoldRamp {
+    pub fn new(clock: impl Clock + 'static, rate: f32, delay: Duration) -> Self {
+        Self {
+            clock: Box::new(clock),
+            rate,
+            delay,
+            held: None,
+        }
+    }
+
+    /// Notes a key going down. Pressing the other direction starts a new hold.
+    pub fn press(&mut self, direction: Direction, modifiers: Modifiers) -> RampPress {
+        if self.held.is_some_and(|hold| hold.direction == direction) {
+            return RampPress::Repeat;
+        }
+        self.held = Some(Hold {
+            direction,
+            modifiers,
+            ramped_until: self.clock.now() + self.delay,
+        });
+        RampPress::Fresh
+    }
+
+    /// Notes a key coming up, which stops the ramp if it was the held one
+    pub fn release(&mut self, direction: Direction) {
+        if self.held.is_some_and(|hold| hold.direction == direction) {
+            self.held = None;
+        }
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held.is_some()
+    }
+
+    /// Gives how far the held key should have moved the volume since the
+    /// last tick, once the delay is over
+    pub fn tick(&mut self) -> Option<(Direction, Modifiers, f32)> {
+        let hold = self.held.as_mut()?;
+        let now = self.clock.now();
+        if now <= hold.ramped_until {
+            return None;
+        }
+        let amount = self.rate * now.duration_since(hold.ramped_until).as_secs_f32();
+        hold.ramped_until = now;
+        Some((hold.direction, hold.modifiers, amount))
+    }
+}
+
+/// Carries out the commands from an input source against one audio backend,
+/// keeping what has to last between them: the target selector, the press
+/// timing for acceleration, the held key ramp, the mute key timing and the
+/// mute states a solo put aside.
+pub struct Controller<S> {
+    targets: TargetSelector<S>,
+    acceleration: VolumeKeyState,
+    ramp: Option<HoldRamp>,
+    mute_hold: Option<MuteHold>,
+    // The mute states from before the current solo
+    solo: Option<Vec<(S, bool)>>,
+}
+
+impl<S: Clone + PartialEq> Controller<S> {
+    pub fn new(config: &Config) -> Self {
+        Self::with_clock(TargetSelector::new(config), &config.hotkeys, &config.acceleration, SystemClock)
+    }
+
+    /// Creates a controller whose key presses are timed by `clock`
+    pub fn with_clock(
+        targets: TargetSelector<S>,
+        hotkeys: &HotkeyConfig,
+        acceleration: &AccelerationConfig,
+        clock: impl Clock + Clone + 'static,
+    ) -> Self {
+        Self {
+            targets,
+            acceleration: VolumeKeyState::with_clock(acceleration, clock.clone()),
+            ramp: acceleration.ramp_per_sec
+                .map(|rate| HoldRamp::new(clock.clone(), rate, Duration::from_millis(acceleration.ramp_delay_ms))),
+            mute_hold: hotkeys.mute_hold_solo_ms.map(|ms| MuteHold::new(clock, Duration::from_millis(ms))),
+            solo: None,
+        }
+    }
+
+    pub fn targets(&mut self) -> &mut TargetSelector<S> {
+        &mut self.targets
+    }
+
+    /// Carries out a command. Returns the volume key to pass through to the
+    /// system, when there was nothing for it to adjust.
+    pub fn handle<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, command: Command, provider: &F, backend: &B) -> Option<VolumeKey> {
+        match command {
+            Command::Key(key, modifiers) => {
+                if key == VolumeKey::Mute && let Some(mute_hold) = &mut self.mute_hold {
+                    // Whether this mutes or solos is only known once the key comes back up
+                    mute_hold.press(modifiers);
+                    return None;
+                }
+                if let Some(direction) = key.direction()
+                    && let Some(ramp) = &mut self.ramp
+                    && ramp.press(direction, modifiers) == RampPress::Repeat
+                {
+                    // Autorepeat only says the key is still down; the ramp sets the pace
+                    self.ramp(provider, backend);
+                    return None;
+                }
+
+                let passed = self.press(key, modifiers, provider, backend);
+                if passed.is_some() {
+                    // Nothing to ramp, so the repeats go to the system like the press did
+                    self.release_ramp(key);
+                }
+                passed
+            }
+            Command::Release(VolumeKey::Mute) => match self.mute_hold.as_mut().and_then(MuteHold::release) {
+                Some(MutePress::Tap(modifiers)) => self.press(VolumeKey::Mute, modifiers, provider, backend),
+                Some(MutePress::Hold) => {
+                    self.toggle_solo(provider, backend);
+                    None
+                }
+                None => None,
+            },
+            Command::Release(key) => {
+                self.release_ramp(key);
+                None
+            }
+            Command::Shutdown => None,
+            Command::TogglePin => {
+                self.targets.toggle_pin(provider, backend);
+                None
+            }
+            Command::ToggleSolo => {
+                self.toggle_solo(provider, backend);
+                None
+            }
+        }
+    }
+
+    fn press<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, key: VolumeKey, modifiers: Modifiers, provider: &F, backend: &B) -> Option<VolumeKey> {
+        match volume::handle_volume_key(key, modifiers, provider, backend, &mut self.targets, &mut self.acceleration) {
+            KeyOutcome::Handled => None,
+            KeyOutcome::PassThrough => Some(key),
+        }
+    }
+
+    fn ramp<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, provider: &F, backend: &B) {
+        if let Some((direction, modifiers, amount)) = self.ramp.as_mut().and_then(HoldRamp::tick) {
+            let scale = self.acceleration.scale();
+            volume::ramp_volume(direction, amount, scale, modifiers, provider, backend, &mut self.targets);
+        }
+    }
+
+    fn release_ramp(&mut self, key: VolumeKey) {
+        if let Some(direction) = key.direction() && let Some(ramp) = &mut self.ramp {
+            ramp.release(direction);
+        }
+    }
+
+    /// Mutes every session except the focused application's, remembering
+    /// which were muted already; the next call puts those states back.
+    pub fn toggle_solo<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, provider: &F, backend: &B) {
+        if let Some(saved) = self.solo.take() {
+            match audio::restore_mute_states(backend, &saved) {
+                Ok(()) => println!("Unsoloed, restored {} mute state(s)", saved.len()),
+                Err(e) => println!("Error restoring mute states: {}", e),
+            }
+            return;
+        }
+
+        // The same application a plain volume key would adjust
+        let keep = match self.targets.select(provider, backend, Modifiers::NONE) {
+            Target::Sessions(sessions) => sessions,
+            Target::Master | Target::PassThrough => {
+                println!("Nothing to solo, the focused application has no audio");
+                return;
+            }
+        };
+
+        let soloed = self.targets.all_sessions(backend)
+            .and_then(|sessions| audio::solo_sessions(backend, &sessions, &keep));
+        match soloed {
+            Ok(saved) => {
+                println!("Soloed {} session(s)", keep.len());
+                self.solo = Some(saved);
+            }
+            Err(e) => println!("Error soloing: {}", e),
+        }
+    }
+
+    /// How long an input loop can wait for the next command before calling
+    /// `wake`, or None if it can wait as long as it takes
+    pub fn next_wake(&self) -> Option<Duration> {
+        if self.ramp.as_ref().is_some_and(HoldRamp::is_held) {
+            Some(RAMP_INTERVAL)
+        } else if self.targets.wants_focus_updates() {
+            Some(FOCUS_POLL_INTERVAL)
+        } else {
+            None
+        }
+    }
+
+    /// Does what is due while no commands come in: ramps a held key, or
+    /// takes note of the focused window for modes that keep a focus history
+    pub fn wake<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, provider: &F, backend: &B) {
+        if self.ramp.as_ref().is_some_and(HoldRamp::is_held) {
+            self.ramp(provider, backend);
+        } else {
+            self.targets.observe_focus(provider, backend);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{MockAudioBackend, MockSession};
+    use crate::clock::FakeClock;
+    use crate::focus::FakeFocusProvider;
+    use crate::matcher::{MatcherConfig, SessionMatcher};
+    use crate::process::ProcessTable;
+    use crate::target::FallbackConfig;
+
+    fn controller(hotkeys: &HotkeyConfig, clock: FakeClock) -> Controller<MockSession> {
+        controller_with(hotkeys, &AccelerationConfig::default(), clock)
+    }
+
+    fn controller_with(hotkeys: &HotkeyConfig, acceleration: &AccelerationConfig, clock: FakeClock) -> Controller<MockSession> {
+        let matcher = SessionMatcher::with_processes(MatcherConfig::default(), ProcessTable::new());
+        let targets = TargetSelector::with_matcher(matcher, FallbackConfig::default());
+        Controller::with_clock(targets, hotkeys, acceleration, clock)
+    }
+
+    fn ramping(rate: f32) -> AccelerationConfig {
+        AccelerationConfig { ramp_per_sec: Some(rate), ramp_delay_ms: 300, ..Default::default() }
+    }
+
+    #[test]
+    fn solo_mutes_the_rest_and_unsolo_restores_it() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let game = backend.add_session(30, "/usr/bin/game");
+        backend.set_muted_of(game, true);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+        let mut controller = controller(&HotkeyConfig::default(), FakeClock::new());
+
+        controller.handle(Command::ToggleSolo, &provider, &backend);
+        assert!(backend.muted_of(spotify) && backend.muted_of(game));
+        assert!(!backend.muted_of(mpv));
+
+        // Focus has moved on by the time it is unsoloed, which makes no difference
+        provider.focus_process(10, "/usr/bin/spotify");
+        controller.handle(Command::ToggleSolo, &provider, &backend);
+        assert!(!backend.muted_of(spotify) && !backend.muted_of(mpv));
+        assert!(backend.muted_of(game), "the game was muted before the solo");
+    }
+
+    #[test]
+    fn solo_without_focused_audio_changes_nothing() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(30, "/usr/bin/gedit");
+        let mut controller = controller(&HotkeyConfig::default(), FakeClock::new());
+
+        controller.handle(Command::ToggleSolo, &provider, &backend);
+        assert!(!backend.muted_of(spotify));
+
+        // So the next toggle solos rather than restoring anything
+        provider.focus_process(10, "/usr/bin/spotify");
+        backend.add_session(20, "/usr/bin/mpv");
+        controller.handle(Command::ToggleSolo, &provider, &backend);
+        assert!(!backend.muted_of(spotify));
+        assert!(backend.muted_of(backend.sessions().unwrap()[1]));
+    }
+
+    #[test]
+    fn holding_mute_solos_and_tapping_it_mutes() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+        let clock = FakeClock::new();
+        let hotkeys = HotkeyConfig { mute_hold_solo_ms: Some(500), ..Default::default() };
+        let mut controller = controller(&hotkeys, clock.clone());
+        let mute = Command::Key(VolumeKey::Mute, Modifiers::NONE);
+
+        // Nothing happens until the key is released
+        controller.handle(mute, &provider, &backend);
+        clock.advance(Duration::from_millis(100));
+        assert!(!backend.muted_of(mpv));
+        controller.handle(Command::Release(VolumeKey::Mute), &provider, &backend);
+        assert!(backend.muted_of(mpv) && !backend.muted_of(spotify));
+
+        // Autorepeat while held doesn't restart the timing
+        controller.handle(mute, &provider, &backend);
+        for _ in 0..5 {
+            clock.advance(Duration::from_millis(100));
+            controller.handle(mute, &provider, &backend);
+        }
+        controller.handle(Command::Release(VolumeKey::Mute), &provider, &backend);
+        assert!(backend.muted_of(spotify), "a hold should solo");
+        assert!(!backend.muted_of(mpv), "the soloed application is unmuted");
+
+        controller.handle(mute, &provider, &backend);
+        clock.advance(Duration::from_millis(500));
+        controller.handle(Command::Release(VolumeKey::Mute), &provider, &backend);
+        assert!(!backend.muted_of(spotify));
+        assert!(backend.muted_of(mpv), "unsolo restores mpv's mute from before");
+    }
+
+    #[test]
+    fn mute_acts_on_press_without_a_hold_threshold() {
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+        let mut controller = controller(&HotkeyConfig::default(), FakeClock::new());
+
+        controller.handle(Command::Key(VolumeKey::Mute, Modifiers::NONE), &provider, &backend);
+        assert!(backend.muted_of(mpv));
+        controller.handle(Command::Release(VolumeKey::Mute), &provider, &backend);
+        assert!(backend.muted_of(mpv));
+    }
+
+    #[test]
+    fn quick_presses_take_bigger_steps() {
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        backend.set_volume_of(mpv, 0.5);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+        let clock = FakeClock::new();
+        let mut controller = controller(&HotkeyConfig::default(), clock.clone());
+        let up = Command::Key(VolumeKey::Up, Modifiers::NONE);
+
+        controller.handle(up, &provider, &backend);
+        assert!((backend.volume_of(mpv) - 0.51).abs() < 1e-6);
+
+        // A second apart is the slowest the curve tells apart, so the step stays the same
+        clock.advance(Duration::from_secs(1));
+        controller.handle(up, &provider, &backend);
+        assert!((backend.volume_of(mpv) - 0.52).abs() < 1e-6);
+
+        for _ in 0..5 {
+            clock.advance(Duration::from_millis(50));
+            controller.handle(up, &provider, &backend);
+        }
+        assert!(backend.volume_of(mpv) > 0.56 + 0.05, "the last press should have accelerated");
+    }
+
+    #[test]
+    fn hold_ramp_ignores_autorepeat_and_follows_the_clock() {
+        let clock = FakeClock::new();
+        let mut ramp = HoldRamp::new(clock.clone(), 0.5, Duration::from_millis(300));
+
+        assert_eq!(ramp.press(Direction::Up, Modifiers::NONE), RampPress::Fresh);
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(ramp.press(Direction::Up, Modifiers::NONE), RampPress::Repeat);
+        assert_eq!(ramp.tick(), None, "the ramp waits out the delay");
+
+        // 100ms past the delay, then another 200ms
+        clock.advance(Duration::from_millis(150));
+        let (direction, _, amount) = ramp.tick().unwrap();
+        assert_eq!(direction, Direction::Up);
+        assert!((amount - 0.05).abs() < 1e-6, "{}", amount);
+        clock.advance(Duration::from_millis(200));
+        assert!((ramp.tick().unwrap().2 - 0.1).abs() < 1e-6);
+
+        // Letting go of the other direction changes nothing, letting go of this one stops it
+        ramp.release(Direction::Down);
+        assert!(ramp.is_held());
+        ramp.release(Direction::Up);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(ramp.tick(), None);
+
+        // Switching direction mid-hold starts over, delay and all
+        ramp.press(Direction::Up, Modifiers::NONE);
+        assert_eq!(ramp.press(Direction::Down, Modifiers::NONE), RampPress::Fresh);
+        clock.advance(Duration::from_millis(300));
+        assert_eq!(ramp.tick(), None);
+    }
+
+    #[test]
+    fn holding_a_key_ramps_at_the_configured_rate() {
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        backend.set_volume_of(mpv, 0.2);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+        let clock = FakeClock::new();
+        let mut controller = controller_with(&HotkeyConfig::default(), &ramping(0.5), clock.clone());
+        let up = Command::Key(VolumeKey::Up, Modifiers::NONE);
+
+        // Down, then a second and a bit of fast autorepeat and timer wakes
+        assert_eq!(controller.next_wake(), None);
+        controller.handle(up, &provider, &backend);
+        assert!((backend.volume_of(mpv) - 0.21).abs() < 1e-6);
+        assert_eq!(controller.next_wake(), Some(RAMP_INTERVAL));
+        for tick in 0..13 {
+            clock.advance(Duration::from_millis(100));
+            if tick % 2 == 0 {
+                controller.handle(up, &provider, &backend);
+            } else {
+                controller.wake(&provider, &backend);
+            }
+        }
+        // Half the volume per second, for the second after the delay
+        assert!((backend.volume_of(mpv) - 0.71).abs() < 1e-4, "{}", backend.volume_of(mpv));
+
+        controller.handle(Command::Release(VolumeKey::Up), &provider, &backend);
+        assert_eq!(controller.next_wake(), None);
+        clock.advance(Duration::from_secs(1));
+        controller.wake(&provider, &backend);
+        assert!((backend.volume_of(mpv) - 0.71).abs() < 1e-4);
+    }
+
+    #[test]
+    fn held_keys_with_nothing_to_adjust_keep_passing_through() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(30, "/usr/bin/gedit");
+        let clock = FakeClock::new();
+        let mut controller = controller_with(&HotkeyConfig::default(), &ramping(0.5), clock.clone());
+        let down = Command::Key(VolumeKey::Down, Modifiers::NONE);
+
+        for _ in 0..3 {
+            assert_eq!(controller.handle(down, &provider, &backend), Some(VolumeKey::Down));
+            clock.advance(Duration::from_millis(400));
+        }
+        assert_eq!(controller.next_wake(), None);
+    }
+}
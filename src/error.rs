@@ -0,0 +1,98 @@
+use std::fmt;
+
+/// Errors returned by the audio backends, focus providers and volume engine
+#[derive(Debug)]
+pub enum Error {
+    /// No window currently has keyboard focus
+    NoFocusedWindow,
+    /// The mouse cursor is over the desktop or no window at all
+    NoWindowUnderCursor,
+    /// The focused window does not say which process owns it (X11 windows without `_NET_WM_PID`)
+    MissingWindowPid { window_id: u64 },
+    /// No audio session belongs to the executable at `path`
+    SessionNotFound { path: String },
+    /// The session went away after it was looked up
+    SessionExpired,
+    /// The executable path of the process could not be found, usually because it is elevated
+    ProcessAccessDenied { pid: u32 },
+    /// The config file could not be parsed or has invalid values
+    InvalidConfig(String),
+    /// The audio backend has no way to do this
+    Unsupported(&'static str),
+    /// Any other failure reported by the platform API
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoFocusedWindow => write!(f, "No window currently has focus"),
+            Error::NoWindowUnderCursor => write!(f, "No window is under the mouse cursor"),
+            Error::MissingWindowPid { window_id } => write!(f, "Window 0x{:x} does not set _NET_WM_PID", window_id),
+            Error::SessionNotFound { path } => write!(f, "No audio session found for {}", path),
+            Error::SessionExpired => write!(f, "Audio session has expired"),
+            Error::ProcessAccessDenied { pid } => write!(f, "Access denied opening process {}", pid),
+            Error::InvalidConfig(message) => write!(f, "Invalid configuration: {}", message),
+            Error::Unsupported(what) => write!(f, "The audio backend does not support {}", what),
+            Error::Backend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Backend(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Backend(Box::new(e))
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::InvalidConfig(e.to_string())
+    }
+}
+
+#[cfg(all(windows, feature = "win32"))]
+impl From<windows::core::Error> for Error {
+    fn from(e: windows::core::Error) -> Self {
+        Error::Backend(Box::new(e))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "pulse"))]
+impl From<libpulse_binding::error::PAErr> for Error {
+    fn from(e: libpulse_binding::error::PAErr) -> Self {
+        Error::Backend(Box::new(e))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "x11"))]
+impl From<x11rb::errors::ConnectError> for Error {
+    fn from(e: x11rb::errors::ConnectError) -> Self {
+        Error::Backend(Box::new(e))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "x11"))]
+impl From<x11rb::errors::ConnectionError> for Error {
+    fn from(e: x11rb::errors::ConnectionError) -> Self {
+        Error::Backend(Box::new(e))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "x11"))]
+impl From<x11rb::errors::ReplyError> for Error {
+    fn from(e: x11rb::errors::ReplyError) -> Self {
+        Error::Backend(Box::new(e))
+    }
+}
@@ -1,112 +1,54 @@
-// Removed unused import: use windows::Win32::Foundation::*;
-use windows::Win32::UI::WindowsAndMessaging::*;
-use std::ffi::OsString;
-use std::os::windows::ffi::OsStringExt;
-// Removing incorrect import and using direct imports from Threading
-// use windows::Win32::System::ProcessStatus::*;
-use windows::Win32::System::Threading::*;
-use windows::core::PWSTR;
-use windows::Win32::Foundation::CloseHandle;
-
-use crate::audio;
-
-pub fn get_focused_window() -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        // Get handle to the foreground window
-        let hwnd = GetForegroundWindow();
-        
-        if hwnd.0 == 0 {
-            println!("No window currently has focus");
-            return Ok(());
-        }
-        
-        // Get the window title
-        let mut title_buffer = [0u16; 512];
-        let length = GetWindowTextW(hwnd, &mut title_buffer);
-        
-        if length > 0 {
-            let title = OsString::from_wide(&title_buffer[..length as usize])
-                .to_string_lossy()
-                .to_string();
-            
-            println!("Focused window: {}", title);
-            println!("Window handle: {:?}", hwnd);
-        } else {
-            println!("Found window with no title, handle: {:?}", hwnd);
-        }
-        
-        Ok(())
-    }
-}
-
-pub fn get_focused_window_pid() -> Result<u32, Box<dyn std::error::Error>> {
-    unsafe {
-        // Get handle to the foreground window
-        let hwnd = GetForegroundWindow();
-
-        if hwnd.0 == 0 {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "No window currently has focus"
-            )));
-        }
-
-        // Get the process ID of the window
-        let mut pid = 0u32;
-        GetWindowThreadProcessId(hwnd, Some(&mut pid));
-
-        Ok(pid)
-    }
+pub mod fake;
+#[cfg(all(windows, feature = "win32"))]
+pub mod win32;
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub mod x11;
+
+pub use fake::FakeFocusProvider;
+#[cfg(all(windows, feature = "win32"))]
+pub use win32::Win32FocusProvider;
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub use x11::X11FocusProvider;
+
+use crate::audio::AudioBackend;
+use crate::error::Result;
+use crate::matcher::{SessionMatch, SessionMatcher};
+
+/// The window that currently has keyboard focus, or is under the mouse cursor
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FocusedWindow {
+    /// ID of the process that owns the window
+    pub pid: u32,
+    /// Full path of the owning process's executable
+    pub path: String,
+    pub title: String,
+    /// Window class (`WM_CLASS` on X11)
+    pub class: String,
+    /// Platform window handle
+    pub window_id: u64,
 }
 
-pub fn get_focused_window_details() -> Result<(u32, String), Box<dyn std::error::Error>> {
-    unsafe {
-        // Get handle to the foreground window
-        let hwnd = GetForegroundWindow();
-
-        if hwnd.0 == 0 {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "No window currently has focus"
-            )));
-        }
-
-        // Get the process ID of the window
-        let mut pid = 0u32;
-        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+/// A source of information about the focused window
+pub trait FocusProvider {
+    fn focused_window(&self) -> Result<FocusedWindow>;
 
-        // Get process executable path
-        let process_path = get_process_path(pid)?;
-        
-        Ok((pid, process_path))
+    /// The top-level window under the mouse cursor, whether or not it has focus.
+    ///
+    /// Providers that can't tell give the focused window.
+    fn window_under_cursor(&self) -> Result<FocusedWindow> {
+        self.focused_window()
     }
 }
 
-pub fn get_process_path(pid: u32) -> Result<String, Box<dyn std::error::Error>> {
-    unsafe {
-        let handle = OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid)?;
-        
-        let mut buffer = [0u16; 260]; // MAX_PATH
-        let mut size = buffer.len() as u32;
-        
-        QueryFullProcessImageNameW(
-            handle, 
-            PROCESS_NAME_FORMAT(0), 
-            PWSTR(buffer.as_mut_ptr()), 
-            &mut size
-        )?;
-        CloseHandle(handle);
-        
-        let path = String::from_utf16_lossy(&buffer[..size as usize]);
-        
-        Ok(path)
-    }
+/// Gets every session playing the focused window's audio.
+///
+/// Browsers, chat clients and games often play through several sessions at
+/// once, so all of those found by the first matching tier are returned.
+pub fn get_focused_window_sessions<F: FocusProvider, B: AudioBackend>(
+    provider: &F,
+    backend: &B,
+    matcher: &mut SessionMatcher<B::Session>,
+) -> Result<SessionMatch<B::Session>> {
+    let window = provider.focused_window()?;
+    matcher.find_sessions(backend, &window)
 }
-
-pub fn get_focused_window_session() -> Result<windows::Win32::Media::Audio::IAudioSessionControl2, Box<dyn std::error::Error>> {
-    unsafe {
-        let (pid, process_path) = get_focused_window_details()?;
-        let session = audio::get_session_by_process_path(&process_path)?;
-        Ok(session)
-    }
-}
\ No newline at end of file
@@ -0,0 +1,107 @@
+use std::sync::{Arc, Mutex};
+use crate::focus::{FocusProvider, FocusedWindow};
+use crate::error::{Error, Result};
+
+/// Focus provider whose focused and hovered windows are set by the caller.
+///
+/// Clones share the same state, so tests can change focus while the code
+/// under test holds its own copy.
+#[derive(Clone, Default)]
+pub struct FakeFocusProvider {
+    window: Arc<Mutex<Option<FocusedWindow>>>,
+    hovered: Arc<Mutex<Option<FocusedWindow>>>,
+}
+
+impl FakeFocusProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives focus to a window
+    pub fn focus(&self, window: FocusedWindow) {
+        *self.window.lock().unwrap() = Some(window);
+    }
+
+    /// Gives focus to a window owned by `pid`, with no title or class
+    pub fn focus_process(&self, pid: u32, path: &str) {
+        self.focus(FocusedWindow {
+            pid,
+            path: path.to_string(),
+            ..Default::default()
+        });
+    }
+
+    /// Leaves no window focused
+    pub fn clear(&self) {
+        *self.window.lock().unwrap() = None;
+    }
+
+    /// Moves the mouse cursor over a window owned by `pid`
+    pub fn hover_process(&self, pid: u32, path: &str) {
+        *self.hovered.lock().unwrap() = Some(FocusedWindow {
+            pid,
+            path: path.to_string(),
+            ..Default::default()
+        });
+    }
+
+    /// Moves the mouse cursor off every window
+    pub fn clear_hover(&self) {
+        *self.hovered.lock().unwrap() = None;
+    }
+}
+
+impl FocusProvider for FakeFocusProvider {
+    fn focused_window(&self) -> Result<FocusedWindow> {
+        match &*self.window.lock().unwrap() {
+            Some(window) => Ok(window.clone()),
+            None => Err(Error::NoFocusedWindow),
+        }
+    }
+
+    fn window_under_cursor(&self) -> Result<FocusedWindow> {
+        match &*self.hovered.lock().unwrap() {
+            Some(window) => Ok(window.clone()),
+            None => Err(Error::NoWindowUnderCursor),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::MockAudioBackend;
+    use crate::matcher::{MatcherConfig, SessionMatcher};
+    use crate::process::ProcessTable;
+    use crate::focus;
+
+    #[test]
+    fn resolves_sessions_of_focused_window() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(10, "/usr/bin/firefox");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let second_mpv = backend.add_session(22, "/usr/bin/mpv");
+
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(21, "/usr/bin/mpv");
+
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), ProcessTable::new());
+        assert_eq!(focus::get_focused_window_sessions(&provider, &backend, &mut matcher).unwrap().sessions, vec![mpv, second_mpv]);
+    }
+
+    #[test]
+    fn fails_without_focus_or_session() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(10, "/usr/bin/firefox");
+
+        let provider = FakeFocusProvider::new();
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), ProcessTable::new());
+        assert!(matches!(focus::get_focused_window_sessions(&provider, &backend, &mut matcher), Err(Error::NoFocusedWindow)));
+
+        provider.focus_process(30, "/usr/bin/gedit");
+        assert!(matches!(
+            focus::get_focused_window_sessions(&provider, &backend, &mut matcher),
+            Err(Error::SessionNotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+use windows::Win32::UI::WindowsAndMessaging::*;
+use windows::Win32::System::Threading::*;
+use windows::core::PWSTR;
+use windows::Win32::Foundation::{CloseHandle, E_ACCESSDENIED, HWND, POINT};
+
+use crate::focus::{FocusProvider, FocusedWindow};
+use crate::error::{Error, Result};
+
+fn get_focused_window_details() -> Result<FocusedWindow> {
+    unsafe {
+        // Get handle to the foreground window
+        let hwnd = GetForegroundWindow();
+
+        if hwnd.0 == 0 {
+            return Err(Error::NoFocusedWindow);
+        }
+
+        get_window_details(hwnd)
+    }
+}
+
+fn get_window_under_cursor_details() -> Result<FocusedWindow> {
+    unsafe {
+        let mut point = POINT::default();
+        GetCursorPos(&mut point)?;
+
+        // WindowFromPoint finds the innermost child, e.g. a video player's render surface
+        let hwnd = GetAncestor(WindowFromPoint(point), GA_ROOT);
+
+        if hwnd.0 == 0 || hwnd == GetDesktopWindow() || hwnd == GetShellWindow() {
+            return Err(Error::NoWindowUnderCursor);
+        }
+
+        get_window_details(hwnd)
+    }
+}
+
+fn get_window_details(hwnd: HWND) -> Result<FocusedWindow> {
+    unsafe {
+        // Get the process ID of the window
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+
+        // Get process executable path
+        let process_path = get_process_path(pid)?;
+
+        // Get the window title and class name
+        let mut title_buffer = [0u16; 512];
+        let title_length = GetWindowTextW(hwnd, &mut title_buffer);
+        let title = String::from_utf16_lossy(&title_buffer[..title_length.max(0) as usize]);
+
+        let mut class_buffer = [0u16; 256];
+        let class_length = GetClassNameW(hwnd, &mut class_buffer);
+        let class = String::from_utf16_lossy(&class_buffer[..class_length.max(0) as usize]);
+
+        Ok(FocusedWindow {
+            pid,
+            path: process_path,
+            title,
+            class,
+            window_id: hwnd.0 as u64,
+        })
+    }
+}
+
+pub fn get_process_path(pid: u32) -> Result<String> {
+    unsafe {
+        let handle = match OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid) {
+            Ok(handle) => handle,
+            Err(e) if e.code() == E_ACCESSDENIED => return Err(Error::ProcessAccessDenied { pid }),
+            Err(e) => return Err(e.into()),
+        };
+        
+        let mut buffer = [0u16; 260]; // MAX_PATH
+        let mut size = buffer.len() as u32;
+        
+        QueryFullProcessImageNameW(
+            handle, 
+            PROCESS_NAME_FORMAT(0), 
+            PWSTR(buffer.as_mut_ptr()), 
+            &mut size
+        )?;
+        CloseHandle(handle);
+        
+        let path = String::from_utf16_lossy(&buffer[..size as usize]);
+        
+        Ok(path)
+    }
+}
+
+/// Focus provider backed by the Win32 foreground window, and `WindowFromPoint`
+/// for the window under the cursor
+pub struct Win32FocusProvider;
+
+impl FocusProvider for Win32FocusProvider {
+    fn focused_window(&self) -> Result<FocusedWindow> {
+        get_focused_window_details()
+    }
+
+    fn window_under_cursor(&self) -> Result<FocusedWindow> {
+        get_window_under_cursor_details()
+    }
+}
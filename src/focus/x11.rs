@@ -0,0 +1,227 @@
+use x11rb::connection::Connection;
+use x11rb::properties::WmClass;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+use crate::focus::{FocusProvider, FocusedWindow};
+use crate::error::{Error, Result};
+
+// Window managers wrap clients in a frame or two, so the client is never far down
+const MAX_FRAME_DEPTH: u32 = 3;
+
+/// Focus provider for X11 window managers that follow EWMH.
+///
+/// The focused window comes from `_NET_ACTIVE_WINDOW` on the root window, the
+/// one under the cursor from `XQueryPointer`, and their owners from `_NET_WM_PID`.
+pub struct X11FocusProvider {
+    conn: RustConnection,
+    root: Window,
+    net_active_window: Atom,
+    net_wm_pid: Atom,
+    net_wm_name: Atom,
+    utf8_string: Atom,
+}
+
+impl X11FocusProvider {
+    /// Connects to the display named by `$DISPLAY`
+    pub fn new() -> Result<Self> {
+        let (conn, screen_num) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen_num].root;
+
+        let net_active_window = intern_atom(&conn, b"_NET_ACTIVE_WINDOW")?;
+        let net_wm_pid = intern_atom(&conn, b"_NET_WM_PID")?;
+        let net_wm_name = intern_atom(&conn, b"_NET_WM_NAME")?;
+        let utf8_string = intern_atom(&conn, b"UTF8_STRING")?;
+
+        Ok(Self {
+            conn,
+            root,
+            net_active_window,
+            net_wm_pid,
+            net_wm_name,
+            utf8_string,
+        })
+    }
+
+    pub fn get_active_window(&self) -> Result<Window> {
+        let reply = self.conn
+            .get_property(false, self.root, self.net_active_window, AtomEnum::WINDOW, 0, 1)?
+            .reply()?;
+
+        match reply.value32().and_then(|mut values| values.next()) {
+            Some(window) if window != x11rb::NONE => Ok(window),
+            _ => Err(Error::NoFocusedWindow),
+        }
+    }
+
+    /// The client window under the pointer, rather than the frame a reparenting
+    /// window manager puts around it
+    pub fn get_window_under_pointer(&self) -> Result<Window> {
+        let child = self.conn.query_pointer(self.root)?.reply()?.child;
+        if child == x11rb::NONE {
+            return Err(Error::NoWindowUnderCursor);
+        }
+
+        Ok(self.find_client(child, MAX_FRAME_DEPTH)?.unwrap_or(child))
+    }
+
+    // Looks through a frame for the topmost window that says which process owns it
+    fn find_client(&self, window: Window, depth: u32) -> Result<Option<Window>> {
+        let reply = self.conn
+            .get_property(false, window, self.net_wm_pid, AtomEnum::CARDINAL, 0, 1)?
+            .reply()?;
+        if reply.value_len > 0 {
+            return Ok(Some(window));
+        }
+        if depth == 0 {
+            return Ok(None);
+        }
+
+        // Children are listed bottom to top
+        for child in self.conn.query_tree(window)?.reply()?.children.into_iter().rev() {
+            if let Some(client) = self.find_client(child, depth - 1)? {
+                return Ok(Some(client));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn get_window_pid(&self, window: Window) -> Result<u32> {
+        let reply = self.conn
+            .get_property(false, window, self.net_wm_pid, AtomEnum::CARDINAL, 0, 1)?
+            .reply()?;
+
+        match reply.value32().and_then(|mut values| values.next()) {
+            Some(pid) => Ok(pid),
+            None => Err(Error::MissingWindowPid { window_id: window as u64 }),
+        }
+    }
+
+    pub fn get_window_title(&self, window: Window) -> Result<String> {
+        let reply = self.conn
+            .get_property(false, window, self.net_wm_name, self.utf8_string, 0, u32::MAX)?
+            .reply()?;
+
+        Ok(String::from_utf8_lossy(&reply.value).into_owned())
+    }
+
+    pub fn get_window_class(&self, window: Window) -> Result<String> {
+        match WmClass::get(&self.conn, window)?.reply()? {
+            Some(wm_class) => Ok(String::from_utf8_lossy(wm_class.class()).into_owned()),
+            None => Ok(String::new()),
+        }
+    }
+
+    fn window_details(&self, window: Window) -> Result<FocusedWindow> {
+        let pid = self.get_window_pid(window)?;
+
+        Ok(FocusedWindow {
+            pid,
+            path: get_process_path(pid)?,
+            title: self.get_window_title(window)?,
+            class: self.get_window_class(window)?,
+            window_id: window as u64,
+        })
+    }
+}
+
+impl FocusProvider for X11FocusProvider {
+    fn focused_window(&self) -> Result<FocusedWindow> {
+        self.window_details(self.get_active_window()?)
+    }
+
+    fn window_under_cursor(&self) -> Result<FocusedWindow> {
+        self.window_details(self.get_window_under_pointer()?)
+    }
+}
+
+/// Resolves the executable of a process through `/proc/<pid>/exe`
+pub fn get_process_path(pid: u32) -> Result<String> {
+    match std::fs::read_link(format!("/proc/{}/exe", pid)) {
+        Ok(path) => Ok(path.to_string_lossy().into_owned()),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Err(Error::ProcessAccessDenied { pid }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn intern_atom(conn: &RustConnection, name: &[u8]) -> Result<Atom> {
+    Ok(conn.intern_atom(false, name)?.reply()?.atom)
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests need an X server without a window manager, e.g.:
+    //!
+    //! ```sh
+    //! Xvfb :99 &
+    //! DISPLAY=:99 cargo test --features x11 -- --ignored
+    //! ```
+
+    use super::*;
+    use x11rb::protocol::xproto::{CreateWindowAux, PropMode, WindowClass};
+    use x11rb::wrapper::ConnectionExt as _;
+
+    // Creates a window and marks it active the way a window manager would
+    fn create_active_window(provider: &X11FocusProvider, pid: Option<u32>) -> Window {
+        let conn = &provider.conn;
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            provider.root,
+            0, 0, 100, 100, 0,
+            WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        ).unwrap();
+
+        conn.change_property8(PropMode::REPLACE, window, AtomEnum::WM_CLASS, AtomEnum::STRING, b"test\0FocusTest\0").unwrap();
+        conn.change_property8(PropMode::REPLACE, window, provider.net_wm_name, provider.utf8_string, "Focus test ✓".as_bytes()).unwrap();
+        if let Some(pid) = pid {
+            conn.change_property32(PropMode::REPLACE, window, provider.net_wm_pid, AtomEnum::CARDINAL, &[pid]).unwrap();
+        }
+        conn.change_property32(PropMode::REPLACE, provider.root, provider.net_active_window, AtomEnum::WINDOW, &[window]).unwrap();
+        conn.sync().unwrap();
+
+        window
+    }
+
+    #[test]
+    #[ignore = "requires an X server such as Xvfb"]
+    fn reads_focused_window_details() {
+        let provider = X11FocusProvider::new().unwrap();
+        let window = create_active_window(&provider, Some(std::process::id()));
+
+        let focused = provider.focused_window().unwrap();
+        assert_eq!(focused.pid, std::process::id());
+        assert_eq!(focused.path, std::env::current_exe().unwrap().to_string_lossy());
+        assert_eq!(focused.title, "Focus test ✓");
+        assert_eq!(focused.class, "FocusTest");
+        assert_eq!(focused.window_id, window as u64);
+    }
+
+    #[test]
+    #[ignore = "requires an X server such as Xvfb"]
+    fn reads_the_window_under_the_pointer() {
+        let provider = X11FocusProvider::new().unwrap();
+        let window = create_active_window(&provider, Some(std::process::id()));
+        provider.conn.map_window(window).unwrap();
+        provider.conn.warp_pointer(x11rb::NONE, window, 0, 0, 0, 0, 50, 50).unwrap();
+        provider.conn.sync().unwrap();
+
+        let hovered = provider.window_under_cursor().unwrap();
+        assert_eq!(hovered.window_id, window as u64);
+        assert_eq!(hovered.pid, std::process::id());
+    }
+
+    #[test]
+    #[ignore = "requires an X server such as Xvfb"]
+    fn reports_windows_without_pid() {
+        let provider = X11FocusProvider::new().unwrap();
+        let window = create_active_window(&provider, None);
+
+        match provider.focused_window() {
+            Err(Error::MissingWindowPid { window_id }) => assert_eq!(window_id, window as u64),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::clock::Clock;
+use crate::focus::FocusedWindow;
+
+// Older applications are very unlikely to still be wanted
+const CAPACITY: usize = 8;
+
+/// Recently focused windows whose applications had audio sessions, newest first.
+///
+/// An application stays in the history while it has focus and for `timeout`
+/// after focus moves elsewhere. Windows are told apart by process, so
+/// focusing another window of the same application refreshes its entry.
+pub struct FocusHistory {
+    clock: Box<dyn Clock>,
+    timeout: Duration,
+    entries: VecDeque<Entry>,
+}
+
+struct Entry {
+    window: FocusedWindow,
+    // When focus moved away from the application, None while it has focus
+    left_at: Option<Instant>,
+}
+
+impl FocusHistory {
+    pub fn new(clock: impl Clock + 'static, timeout: Duration) -> Self {
+        Self {
+            clock: Box::new(clock),
+            timeout,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Records what has focus now. `window` is None when nothing is focused,
+    /// and `has_audio` says whether its application has any sessions.
+    pub fn observe(&mut self, window: Option<&FocusedWindow>, has_audio: bool) {
+        let now = self.clock.now();
+        let pid = window.map(|window| window.pid);
+
+        for entry in &mut self.entries {
+            if entry.left_at.is_none() && Some(entry.window.pid) != pid {
+                entry.left_at = Some(now);
+            }
+        }
+
+        if let Some(window) = window.filter(|_| has_audio) {
+            self.entries.retain(|entry| entry.window.pid != window.pid);
+            self.entries.push_front(Entry { window: window.clone(), left_at: None });
+            self.entries.truncate(CAPACITY);
+        }
+
+        let timeout = self.timeout;
+        self.entries.retain(|entry| entry.left_at.is_none_or(|left_at| now.duration_since(left_at) < timeout));
+    }
+
+    /// The windows that haven't expired yet, most recently focused first
+    pub fn recent(&self) -> Vec<&FocusedWindow> {
+        let now = self.clock.now();
+        self.entries.iter()
+            .filter(|entry| entry.left_at.is_none_or(|left_at| now.duration_since(left_at) < self.timeout))
+            .map(|entry| &entry.window)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+
+    fn window(pid: u32, path: &str) -> FocusedWindow {
+        FocusedWindow {
+            pid,
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn pids(history: &FocusHistory) -> Vec<u32> {
+        history.recent().iter().map(|window| window.pid).collect()
+    }
+
+    #[test]
+    fn keeps_windows_with_audio_newest_first() {
+        let mut history = FocusHistory::new(FakeClock::new(), Duration::from_secs(60));
+        let spotify = window(10, "/usr/bin/spotify");
+        let mpv = window(20, "/usr/bin/mpv");
+        let editor = window(30, "/usr/bin/gedit");
+
+        history.observe(Some(&spotify), true);
+        history.observe(Some(&editor), false);
+        history.observe(Some(&mpv), true);
+        history.observe(None, false);
+        assert_eq!(pids(&history), vec![20, 10]);
+
+        // Coming back moves it to the front instead of adding it twice
+        history.observe(Some(&spotify), true);
+        assert_eq!(pids(&history), vec![10, 20]);
+    }
+
+    #[test]
+    fn entries_expire_after_losing_focus() {
+        let clock = FakeClock::new();
+        let mut history = FocusHistory::new(clock.clone(), Duration::from_secs(60));
+        let spotify = window(10, "/usr/bin/spotify");
+        let mpv = window(20, "/usr/bin/mpv");
+        let browser = window(30, "/usr/lib/firefox/firefox");
+
+        // Focused for a long time, so only the time since it lost focus counts
+        history.observe(Some(&spotify), true);
+        clock.advance(Duration::from_secs(600));
+        history.observe(Some(&mpv), true);
+        clock.advance(Duration::from_secs(30));
+        history.observe(Some(&browser), false);
+        assert_eq!(pids(&history), vec![20, 10]);
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(pids(&history), vec![20], "spotify lost focus 60s ago");
+
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(pids(&history), vec![20]);
+        clock.advance(Duration::from_secs(1));
+        assert!(history.recent().is_empty());
+    }
+
+    #[test]
+    fn focused_application_never_expires() {
+        let clock = FakeClock::new();
+        let mut history = FocusHistory::new(clock.clone(), Duration::from_secs(5));
+        let mpv = window(20, "/usr/bin/mpv");
+
+        history.observe(Some(&mpv), true);
+        clock.advance(Duration::from_secs(3600));
+        history.observe(Some(&mpv), false);
+        assert_eq!(pids(&history), vec![20], "another window of the same process kept focus");
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut history = FocusHistory::new(FakeClock::new(), Duration::from_secs(60));
+        for pid in 0..20 {
+            history.observe(Some(&window(100 + pid, "/usr/bin/player")), true);
+        }
+
+        let recent = pids(&history);
+        assert_eq!(recent.len(), CAPACITY);
+        assert_eq!(recent[0], 119);
+    }
+}
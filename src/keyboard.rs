@@ -1,232 +1,187 @@
-use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
-use std::ptr::null_mut;
-use std::ffi::c_void;
-use std::time::{Instant, Duration};
-use std::collections::VecDeque;
-use windows::Win32::Foundation::*;
-use windows::Win32::UI::WindowsAndMessaging::*;
-use crate::audio;
-use crate::focus;
-use std::sync::Mutex;
-
-static HOOK_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
-
-// Thread-safe implementation using Mutex
-lazy_static::lazy_static! {
-    static ref VOLUME_STATE: Mutex<VolumeKeyState> = Mutex::new(VolumeKeyState {
-        last_pressed: None,
-        base_increment: 0.01,
-        time_deltas: [1000; 5], // Initialize with 1000ms (1 second)
-        current_index: 0,
-        history_size: 0,
-        max_history_size: 5,
-        max_acceleration: 15.0,
-        min_acceleration: 1.0,
-        decay_rate: 0.016,
-    });
+#[cfg(all(windows, feature = "win32"))]
+pub mod win32;
+#[cfg(all(target_os = "linux", feature = "evdev"))]
+pub mod evdev;
+
+#[cfg(all(windows, feature = "win32"))]
+pub use win32::{install_keyboard_hook, pass_through, uninstall_keyboard_hook};
+
+use std::fmt;
+use serde::{Deserialize, Deserializer};
+use crate::error::{Error, Result};
+use crate::worker::Command;
+
+/// A modifier key, as named in the config file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
 }
 
-// Struct to track volume key state
-struct VolumeKeyState {
-    last_pressed: Option<Instant>,
-    base_increment: f32,
-    // Fixed-size array for storing time deltas (in milliseconds)
-    time_deltas: [u64; 5],
-    current_index: usize,
-    history_size: usize,
-    max_history_size: usize,
-    max_acceleration: f32,
-    min_acceleration: f32,
-    decay_rate: f32,
+/// The modifier keys held down while a volume key was pressed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
 }
 
-// Virtual key codes for media keys - using u32 to match KBDLLHOOKSTRUCT.vkCode type
-const VK_VOLUME_MUTE: u32 = 0xAD;
-const VK_VOLUME_DOWN: u32 = 0xAE;
-const VK_VOLUME_UP: u32 = 0xAF;
-
-// Callback function for keyboard hook
-extern "system" fn keyboard_hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-    unsafe {
-        if code >= 0 {
-            let kb_struct = *(lparam.0 as *const KBDLLHOOKSTRUCT);
-            
-            // Check if it's a volume key event
-            match kb_struct.vkCode {
-                VK_VOLUME_UP => {
-                    if wparam.0 == WM_KEYDOWN as usize {
-                        handle_volume_up();
-                        return LRESULT(1); // Prevent default behavior
-                    }
-                },
-                VK_VOLUME_DOWN => {
-                    if wparam.0 == WM_KEYDOWN as usize {
-                        handle_volume_down();
-                        return LRESULT(1); // Prevent default behavior
-                    }
-                },
-                VK_VOLUME_MUTE => {
-                    if wparam.0 == WM_KEYDOWN as usize {
-                        handle_volume_mute();
-                        return LRESULT(1); // Prevent default behavior
-                    }
-                },
-                _ => {}
-            }
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers { shift: false, ctrl: false, alt: false };
+
+    pub fn holds(&self, modifier: Modifier) -> bool {
+        match modifier {
+            Modifier::Shift => self.shift,
+            Modifier::Ctrl => self.ctrl,
+            Modifier::Alt => self.alt,
         }
-        
-        // Call the next hook in the chain for non-volume keys
-        CallNextHookEx(HHOOK(HOOK_HANDLE.load(Ordering::SeqCst) as isize), code, wparam, lparam)
     }
 }
 
-pub fn install_keyboard_hook() -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        let hook = SetWindowsHookExW(
-            WH_KEYBOARD_LL,
-            Some(keyboard_hook_proc),
-            HINSTANCE(0),
-            0,
-        )?;
-        
-        // Store the hook handle
-        HOOK_HANDLE.store(hook.0 as *mut c_void, Ordering::SeqCst);
-        println!("Keyboard hook installed successfully. Listening for volume keys...");
-        
-        Ok(())
-    }
+/// A global shortcut such as `ctrl+alt+p`: some modifiers and a letter or digit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Hotkey {
+    pub modifiers: Modifiers,
+    /// An uppercase ASCII letter or a digit, which is also its Win32 virtual key code
+    pub key: char,
 }
 
-pub fn uninstall_keyboard_hook() -> Result<(), Box<dyn std::error::Error>> {
-    let hook_ptr = HOOK_HANDLE.load(Ordering::SeqCst);
-    if !hook_ptr.is_null() {
-        unsafe {
-            UnhookWindowsHookEx(HHOOK(hook_ptr as isize))?;
-            HOOK_HANDLE.store(null_mut(), Ordering::SeqCst);
-            println!("Keyboard hook uninstalled");
+impl Hotkey {
+    pub fn parse(text: &str) -> Result<Self> {
+        let invalid = || Error::InvalidConfig(format!("Invalid hotkey {:?}, expected something like \"ctrl+alt+p\"", text));
+
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        let key = match parts.pop().map(|key| key.to_ascii_uppercase()) {
+            Some(key) if key.len() == 1 && key.chars().all(|c| c.is_ascii_alphanumeric()) => key.chars().next().unwrap(),
+            _ => return Err(invalid()),
+        };
+
+        let mut modifiers = Modifiers::NONE;
+        for part in parts {
+            match part.to_ascii_lowercase().as_str() {
+                "shift" => modifiers.shift = true,
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "alt" => modifiers.alt = true,
+                _ => return Err(invalid()),
+            }
         }
-    }
-    Ok(())
-} 
 
+        // A bare letter would fire while typing
+        if !modifiers.ctrl && !modifiers.alt {
+            return Err(invalid());
+        }
+        Ok(Self { modifiers, key })
+    }
 
-fn handle_volume_mute() {
-    match focus::get_focused_window_session() {
-        Ok(session) => audio::toggle_session_mute(&session).unwrap(),
-        Err(e) => println!("Error getting focused window session: {:?}", e)
+    /// Whether pressing `key` with exactly `modifiers` held is this hotkey
+    pub fn matches(&self, modifiers: Modifiers, key: char) -> bool {
+        self.modifiers == modifiers && self.key == key.to_ascii_uppercase()
     }
 }
 
+impl TryFrom<String> for Hotkey {
+    type Error = Error;
 
-fn calculate_volume_adjustment() -> f32 {
-    let now = Instant::now();
-    
-    // Use a mutex to safely access our state
-    let mut state = VOLUME_STATE.lock().unwrap();
-    
-    // Calculate time since last press
-    let elapsed = if let Some(last) = state.last_pressed {
-        now.duration_since(last)
-    } else {
-        Duration::from_secs(1)
-    };
-    
-    // Update last pressed time
-    state.last_pressed = Some(now);
-    
-    // Convert to milliseconds
-    let elapsed_ms = elapsed.as_millis() as u64;
-    
-    // Get the current index first, to avoid the borrow conflict
-    let current_idx = state.current_index;
-    
-    // Update our circular buffer of time deltas
-    state.time_deltas[current_idx] = elapsed_ms;
-    state.current_index = (current_idx + 1) % state.max_history_size;
-    if state.history_size < state.max_history_size {
-        state.history_size += 1;
+    fn try_from(text: String) -> Result<Self> {
+        Self::parse(&text)
     }
-    
-    // Calculate average time delta
-    let avg_elapsed_ms = if state.history_size == 0 {
-        elapsed_ms
-    } else {
-        let sum: u64 = state.time_deltas.iter().take(state.history_size).sum();
-        sum / state.history_size as u64
-    };
-    
-    // Use the parameters from the state instead of hardcoded values
-    let acceleration_factor = state.max_acceleration * (-state.decay_rate * avg_elapsed_ms as f32).exp();
-    let acceleration_factor = acceleration_factor.max(state.min_acceleration).min(state.max_acceleration);
-    
-    // Debug output
-    println!("Time delta: {}ms, Avg delta: {}ms, Acceleration: {:.2}x", 
-             elapsed_ms, avg_elapsed_ms, acceleration_factor);
-    
-    // Return the adjusted increment
-    state.base_increment * acceleration_factor
 }
 
-fn handle_volume_up() {
-    match focus::get_focused_window_session() {
-        Ok(session) => {
-            // Get the current volume
-            let current_volume = match audio::get_session_volume(&session) {
-                Ok(vol) => vol,
-                Err(e) => {
-                    println!("Error getting volume: {:?}", e);
-                    return;
-                }
-            };
-
-            // Calculate adaptive adjustment
-            let adjustment = calculate_volume_adjustment();
-            let new_volume = (current_volume + adjustment).clamp(0.0, 1.0);
-
-            // Set the new volume
-            if let Err(e) = audio::set_session_volume(&session, new_volume) {
-                println!("Error setting volume: {:?}", e);
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [(self.modifiers.ctrl, "Ctrl+"), (self.modifiers.alt, "Alt+"), (self.modifiers.shift, "Shift+")] {
+            if held {
+                write!(f, "{}", name)?;
             }
         }
-        Err(e) => println!("Error getting focused window session: {:?}", e)
+        write!(f, "{}", self.key)
     }
 }
 
-fn handle_volume_down() {
-    match focus::get_focused_window_session() {
-        Ok(session) => {
-            // Get the current volume
-            let current_volume = match audio::get_session_volume(&session) {
-                Ok(vol) => vol,
-                Err(e) => {
-                    println!("Error getting volume: {:?}", e);
-                    return;
-                }
-            };
-
-            // Calculate adaptive adjustment
-            let adjustment = calculate_volume_adjustment();
-            let new_volume = (current_volume - adjustment).clamp(0.0, 1.0);
-
-            // Set the new volume
-            if let Err(e) = audio::set_session_volume(&session, new_volume) {
-                println!("Error setting volume: {:?}", e);
-            }
+/// Settings read from the `[hotkeys]` table of the config file
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotkeyConfig {
+    /// Pins the volume keys to the focused application, or unpins them.
+    /// `"none"` turns a hotkey off.
+    #[serde(deserialize_with = "optional_hotkey")]
+    pub pin: Option<Hotkey>,
+    /// Mutes everything but the focused application, or puts the mute states back
+    #[serde(deserialize_with = "optional_hotkey")]
+    pub solo: Option<Hotkey>,
+    /// Holding the mute key this long solos instead of muting. Off unless
+    /// set, since mute then waits for the key to be released.
+    pub mute_hold_solo_ms: Option<u64>,
+}
+
+impl Default for HotkeyConfig {
+    fn default() -> Self {
+        let ctrl_alt = Modifiers { ctrl: true, alt: true, ..Modifiers::NONE };
+        Self {
+            pin: Some(Hotkey { modifiers: ctrl_alt, key: 'P' }),
+            solo: Some(Hotkey { modifiers: ctrl_alt, key: 'S' }),
+            mute_hold_solo_ms: None,
+        }
+    }
+}
+
+impl HotkeyConfig {
+    /// The command for pressing `key` with `modifiers` held, if that is one of the hotkeys
+    pub fn command(&self, modifiers: Modifiers, key: char) -> Option<Command> {
+        if self.pin.is_some_and(|hotkey| hotkey.matches(modifiers, key)) {
+            Some(Command::TogglePin)
+        } else if self.solo.is_some_and(|hotkey| hotkey.matches(modifiers, key)) {
+            Some(Command::ToggleSolo)
+        } else {
+            None
         }
-        Err(e) => println!("Error getting focused window session: {:?}", e)
     }
 }
 
-pub fn set_acceleration_parameters(max: f32, min: f32, decay: f32) {
-    if let Ok(mut state) = VOLUME_STATE.lock() {
-        state.max_acceleration = max;
-        state.min_acceleration = min;
-        state.decay_rate = decay;
+fn optional_hotkey<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Hotkey>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    if text.eq_ignore_ascii_case("none") {
+        return Ok(None);
     }
+    Hotkey::parse(&text).map(Some).map_err(serde::de::Error::custom)
 }
 
-pub fn set_base_increment(increment: f32) {
-    if let Ok(mut state) = VOLUME_STATE.lock() {
-        state.base_increment = increment;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hotkeys() {
+        let ctrl_alt = Modifiers { ctrl: true, alt: true, ..Modifiers::NONE };
+        let cases = [
+            ("ctrl+alt+p", Some(Hotkey { modifiers: ctrl_alt, key: 'P' })),
+            ("Ctrl + Alt + 5", Some(Hotkey { modifiers: ctrl_alt, key: '5' })),
+            ("control+shift+m", Some(Hotkey { modifiers: Modifiers { ctrl: true, shift: true, ..Modifiers::NONE }, key: 'M' })),
+            ("p", None),
+            ("shift+p", None),
+            ("ctrl+alt+f1", None),
+            ("ctrl+win+p", None),
+            ("", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(Hotkey::parse(text).ok(), expected, "{:?}", text);
+        }
+        assert_eq!(Hotkey::parse("alt+shift+ctrl+s").unwrap().to_string(), "Ctrl+Alt+Shift+S");
+    }
+
+    #[test]
+    fn hotkeys_need_exactly_their_modifiers() {
+        let hotkeys = HotkeyConfig::default();
+        let ctrl_alt = Modifiers { ctrl: true, alt: true, ..Modifiers::NONE };
+
+        assert_eq!(hotkeys.command(ctrl_alt, 'p'), Some(Command::TogglePin));
+        assert_eq!(hotkeys.command(ctrl_alt, 'S'), Some(Command::ToggleSolo));
+        assert_eq!(hotkeys.command(ctrl_alt, 'x'), None);
+        assert_eq!(hotkeys.command(Modifiers { shift: true, ..ctrl_alt }, 'p'), None);
+        assert_eq!(HotkeyConfig { pin: None, ..Default::default() }.command(ctrl_alt, 'p'), None);
     }
 }
@@ -0,0 +1,352 @@
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, Device, EventSummary, InputEvent, KeyCode};
+use crate::audio::AudioBackend;
+use crate::config::Config;
+use crate::error::Result;
+use crate::focus::FocusProvider;
+use crate::controller::Controller;
+use crate::keyboard::{HotkeyConfig, Modifiers};
+use crate::volume::VolumeKey;
+use crate::worker::{self, Command};
+
+/// Name of the uinput device that re-emits everything except volume keys
+pub const PASSTHROUGH_DEVICE_NAME: &str = "focused-window-volume passthrough";
+
+/// Maps an evdev key code to the volume key it represents
+pub fn volume_key(code: KeyCode) -> Option<VolumeKey> {
+    match code {
+        KeyCode::KEY_VOLUMEUP => Some(VolumeKey::Up),
+        KeyCode::KEY_VOLUMEDOWN => Some(VolumeKey::Down),
+        KeyCode::KEY_MUTE => Some(VolumeKey::Mute),
+        _ => None,
+    }
+}
+
+/// Notes a key event in the modifiers held on one device: 1 is a press, 2 autorepeat and 0 a release
+pub fn update_modifiers(modifiers: &mut Modifiers, code: KeyCode, value: i32) {
+    let held = value != 0;
+    match code {
+        KeyCode::KEY_LEFTSHIFT | KeyCode::KEY_RIGHTSHIFT => modifiers.shift = held,
+        KeyCode::KEY_LEFTCTRL | KeyCode::KEY_RIGHTCTRL => modifiers.ctrl = held,
+        KeyCode::KEY_LEFTALT | KeyCode::KEY_RIGHTALT => modifiers.alt = held,
+        _ => {}
+    }
+}
+
+/// Maps the letter and digit keys to the character hotkeys name them by
+pub fn key_char(code: KeyCode) -> Option<char> {
+    let c = match code {
+        KeyCode::KEY_A => 'A', KeyCode::KEY_B => 'B', KeyCode::KEY_C => 'C', KeyCode::KEY_D => 'D',
+        KeyCode::KEY_E => 'E', KeyCode::KEY_F => 'F', KeyCode::KEY_G => 'G', KeyCode::KEY_H => 'H',
+        KeyCode::KEY_I => 'I', KeyCode::KEY_J => 'J', KeyCode::KEY_K => 'K', KeyCode::KEY_L => 'L',
+        KeyCode::KEY_M => 'M', KeyCode::KEY_N => 'N', KeyCode::KEY_O => 'O', KeyCode::KEY_P => 'P',
+        KeyCode::KEY_Q => 'Q', KeyCode::KEY_R => 'R', KeyCode::KEY_S => 'S', KeyCode::KEY_T => 'T',
+        KeyCode::KEY_U => 'U', KeyCode::KEY_V => 'V', KeyCode::KEY_W => 'W', KeyCode::KEY_X => 'X',
+        KeyCode::KEY_Y => 'Y', KeyCode::KEY_Z => 'Z',
+        KeyCode::KEY_0 => '0', KeyCode::KEY_1 => '1', KeyCode::KEY_2 => '2', KeyCode::KEY_3 => '3',
+        KeyCode::KEY_4 => '4', KeyCode::KEY_5 => '5', KeyCode::KEY_6 => '6', KeyCode::KEY_7 => '7',
+        KeyCode::KEY_8 => '8', KeyCode::KEY_9 => '9',
+        _ => return None,
+    };
+    Some(c)
+}
+
+// The command of the hotkey pressing `code` completes, if any
+fn hotkey_command(hotkeys: &HotkeyConfig, modifiers: Modifiers, code: KeyCode) -> Option<Command> {
+    key_char(code).and_then(|key| hotkeys.command(modifiers, key))
+}
+
+/// Opens every input device that has at least one volume key
+pub fn open_volume_keyboards() -> std::io::Result<Vec<Device>> {
+    let devices: Vec<Device> = evdev::enumerate()
+        .map(|(_, device)| device)
+        .filter(|device| {
+            device.supported_keys().is_some_and(|keys| {
+                keys.iter().any(|key| volume_key(key).is_some())
+            })
+        })
+        .collect();
+
+    if devices.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No input device has volume keys"));
+    }
+
+    Ok(devices)
+}
+
+/// The uinput device that grabbed events are re-emitted through
+#[derive(Clone)]
+pub struct Passthrough {
+    device: Arc<Mutex<VirtualDevice>>,
+}
+
+impl Passthrough {
+    /// Taps a volume key that was consumed after all, so the system handles it
+    pub fn send(&self, key: VolumeKey) -> std::io::Result<()> {
+        let code = match key {
+            VolumeKey::Up => KeyCode::KEY_VOLUMEUP,
+            VolumeKey::Down => KeyCode::KEY_VOLUMEDOWN,
+            VolumeKey::Mute => KeyCode::KEY_MUTE,
+        };
+        let mut device = self.device.lock().unwrap();
+        device.emit(&[*evdev::KeyEvent::new(code, 1)])?;
+        device.emit(&[*evdev::KeyEvent::new(code, 0)])
+    }
+}
+
+/// Grabs the devices and starts reading them on background threads.
+///
+/// Volume key presses (including autorepeat) and releases, and the hotkeys,
+/// are sent to the returned receiver and consumed; every other event is
+/// re-emitted through a uinput device so the rest of the keyboard keeps working.
+pub fn start(devices: Vec<Device>, hotkeys: &HotkeyConfig) -> std::io::Result<(mpsc::Receiver<Command>, Passthrough)> {
+    // The passthrough device needs every key any grabbed device can produce
+    let mut keys = AttributeSet::<KeyCode>::new();
+    for device in &devices {
+        if let Some(supported) = device.supported_keys() {
+            for key in supported.iter() {
+                keys.insert(key);
+            }
+        }
+    }
+
+    let passthrough = VirtualDevice::builder()?
+        .name(PASSTHROUGH_DEVICE_NAME)
+        .with_keys(&keys)?
+        .build()?;
+    let passthrough = Passthrough { device: Arc::new(Mutex::new(passthrough)) };
+
+    let (tx, rx) = mpsc::channel();
+    for mut device in devices {
+        device.grab()?;
+
+        let tx = tx.clone();
+        let passthrough = passthrough.device.clone();
+        let hotkeys = hotkeys.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = forward_events(&mut device, &passthrough, &tx, &hotkeys) {
+                println!("Error reading input device {:?}: {:?}", device.name(), e);
+            }
+        });
+    }
+
+    Ok((rx, passthrough))
+}
+
+// Reads one grabbed device until it disappears or the receiver is dropped
+fn forward_events(device: &mut Device, passthrough: &Mutex<VirtualDevice>, tx: &mpsc::Sender<Command>, hotkeys: &HotkeyConfig) -> std::io::Result<()> {
+    let mut modifiers = Modifiers::default();
+    // The last key of a hotkey while it is down, so its autorepeat and release are consumed too
+    let mut hotkey_held: Option<KeyCode> = None;
+
+    loop {
+        let mut forwarded: Vec<InputEvent> = Vec::new();
+
+        for event in device.fetch_events()? {
+            match event.destructure() {
+                EventSummary::Key(_, code, value) if volume_key(code).is_some() => {
+                    // Key down (1) and autorepeat (2) both adjust the volume, key up (0) ends a long press
+                    let key = volume_key(code).unwrap();
+                    let command = if value == 0 { Command::Release(key) } else { Command::Key(key, modifiers) };
+                    if tx.send(command).is_err() {
+                        return Ok(());
+                    }
+                }
+                EventSummary::Key(_, code, value) if hotkey_held == Some(code) => {
+                    if value == 0 {
+                        hotkey_held = None;
+                    }
+                }
+                EventSummary::Key(_, code, 1) if hotkey_command(hotkeys, modifiers, code).is_some() => {
+                    if tx.send(hotkey_command(hotkeys, modifiers, code).unwrap()).is_err() {
+                        return Ok(());
+                    }
+                    hotkey_held = Some(code);
+                }
+                // `emit` terminates each batch with its own SYN_REPORT
+                EventSummary::Synchronization(..) => {}
+                EventSummary::Key(_, code, value) => {
+                    update_modifiers(&mut modifiers, code, value);
+                    forwarded.push(event);
+                }
+                _ => forwarded.push(event),
+            }
+        }
+
+        if !forwarded.is_empty() {
+            passthrough.lock().unwrap().emit(&forwarded)?;
+        }
+    }
+}
+
+/// Captures volume keys from every capable device and handles them until the devices go away
+pub fn run_input_source<F: FocusProvider, B: AudioBackend>(provider: &F, backend: &B, config: &Config) -> Result<()> {
+    let (commands, passthrough) = start(open_volume_keyboards()?, &config.hotkeys)?;
+    println!("Grabbed evdev devices. Listening for volume keys...");
+
+    let mut controller = Controller::new(config);
+    while let Some(command) = worker::next_command(&commands, provider, Some((backend, &mut controller))) {
+        if let Some(key) = controller.handle(command, provider, backend)
+            && let Err(e) = passthrough.send(key)
+        {
+            println!("Error passing key through: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests create uinput devices, so they need write access to
+    //! `/dev/uinput` and read access to `/dev/input/event*`.
+
+    use super::*;
+    use std::time::Duration;
+
+    fn virtual_keyboard() -> (VirtualDevice, Device) {
+        let keys: AttributeSet<KeyCode> = [
+            KeyCode::KEY_A,
+            KeyCode::KEY_P,
+            KeyCode::KEY_LEFTCTRL,
+            KeyCode::KEY_LEFTALT,
+            KeyCode::KEY_VOLUMEUP,
+            KeyCode::KEY_VOLUMEDOWN,
+            KeyCode::KEY_MUTE,
+        ].into_iter().collect();
+
+        let mut keyboard = VirtualDevice::builder().unwrap()
+            .name("focused-window-volume test keyboard")
+            .with_keys(&keys).unwrap()
+            .build().unwrap();
+
+        // Give udev a moment to create the device node
+        std::thread::sleep(Duration::from_millis(200));
+        let node = keyboard.enumerate_dev_nodes_blocking().unwrap()
+            .find_map(|node| node.ok())
+            .expect("virtual keyboard has no device node");
+
+        (keyboard, Device::open(node).unwrap())
+    }
+
+    fn passthrough_device() -> Device {
+        std::thread::sleep(Duration::from_millis(200));
+        evdev::enumerate()
+            .map(|(_, device)| device)
+            .find(|device| device.name() == Some(PASSTHROUGH_DEVICE_NAME))
+            .expect("passthrough device was not created")
+    }
+
+    fn tap(keyboard: &mut VirtualDevice, code: KeyCode) {
+        keyboard.emit(&[*evdev::KeyEvent::new(code, 1)]).unwrap();
+        keyboard.emit(&[*evdev::KeyEvent::new(code, 0)]).unwrap();
+    }
+
+    #[test]
+    fn classifies_volume_keys() {
+        assert_eq!(volume_key(KeyCode::KEY_VOLUMEUP), Some(VolumeKey::Up));
+        assert_eq!(volume_key(KeyCode::KEY_VOLUMEDOWN), Some(VolumeKey::Down));
+        assert_eq!(volume_key(KeyCode::KEY_MUTE), Some(VolumeKey::Mute));
+        assert_eq!(volume_key(KeyCode::KEY_A), None);
+    }
+
+    #[test]
+    #[ignore = "requires access to /dev/uinput"]
+    fn consumes_volume_keys_and_passes_other_keys_through() {
+        let (mut keyboard, device) = virtual_keyboard();
+        let (keys, _) = start(vec![device], &HotkeyConfig::default()).unwrap();
+        let mut passthrough = passthrough_device();
+
+        tap(&mut keyboard, KeyCode::KEY_VOLUMEUP);
+        tap(&mut keyboard, KeyCode::KEY_A);
+        tap(&mut keyboard, KeyCode::KEY_MUTE);
+
+        let received: Vec<Command> = (0..4).map(|_| keys.recv_timeout(Duration::from_secs(1)).unwrap()).collect();
+        assert_eq!(received, vec![
+            Command::Key(VolumeKey::Up, Modifiers::NONE),
+            Command::Release(VolumeKey::Up),
+            Command::Key(VolumeKey::Mute, Modifiers::NONE),
+            Command::Release(VolumeKey::Mute),
+        ]);
+
+        // Only KEY_A should reach the passthrough device
+        let mut seen = Vec::new();
+        while seen.len() < 2 {
+            for event in passthrough.fetch_events().unwrap() {
+                if let EventSummary::Key(_, code, value) = event.destructure() {
+                    seen.push((code, value));
+                }
+            }
+        }
+        assert_eq!(seen, vec![(KeyCode::KEY_A, 1), (KeyCode::KEY_A, 0)]);
+    }
+
+    #[test]
+    fn tracks_modifiers_and_hotkeys() {
+        let hotkeys = HotkeyConfig::default();
+        let mut modifiers = Modifiers::default();
+        assert_eq!(hotkey_command(&hotkeys, modifiers, KeyCode::KEY_P), None);
+
+        update_modifiers(&mut modifiers, KeyCode::KEY_LEFTCTRL, 1);
+        assert_eq!(hotkey_command(&hotkeys, modifiers, KeyCode::KEY_P), None, "Ctrl alone isn't enough");
+        update_modifiers(&mut modifiers, KeyCode::KEY_RIGHTALT, 1);
+        update_modifiers(&mut modifiers, KeyCode::KEY_RIGHTALT, 2);
+        assert_eq!(hotkey_command(&hotkeys, modifiers, KeyCode::KEY_P), Some(Command::TogglePin));
+        assert_eq!(hotkey_command(&hotkeys, modifiers, KeyCode::KEY_S), Some(Command::ToggleSolo));
+        assert_eq!(hotkey_command(&hotkeys, modifiers, KeyCode::KEY_A), None);
+        assert_eq!(hotkey_command(&hotkeys, modifiers, KeyCode::KEY_F1), None);
+
+        update_modifiers(&mut modifiers, KeyCode::KEY_LEFTSHIFT, 1);
+        update_modifiers(&mut modifiers, KeyCode::KEY_LEFTCTRL, 0);
+        assert_eq!(modifiers, Modifiers { shift: true, ctrl: false, alt: true });
+    }
+
+    #[test]
+    #[ignore = "requires access to /dev/uinput"]
+    fn consumes_hotkeys() {
+        let (mut keyboard, device) = virtual_keyboard();
+        let (keys, _) = start(vec![device], &HotkeyConfig::default()).unwrap();
+        let mut passthrough = passthrough_device();
+
+        keyboard.emit(&[*evdev::KeyEvent::new(KeyCode::KEY_LEFTCTRL, 1)]).unwrap();
+        keyboard.emit(&[*evdev::KeyEvent::new(KeyCode::KEY_LEFTALT, 1)]).unwrap();
+        tap(&mut keyboard, KeyCode::KEY_P);
+        keyboard.emit(&[*evdev::KeyEvent::new(KeyCode::KEY_LEFTALT, 0)]).unwrap();
+        keyboard.emit(&[*evdev::KeyEvent::new(KeyCode::KEY_LEFTCTRL, 0)]).unwrap();
+
+        assert_eq!(keys.recv_timeout(Duration::from_secs(1)).unwrap(), Command::TogglePin);
+
+        // The modifiers still reach the system, the P doesn't
+        let mut seen = Vec::new();
+        while seen.len() < 4 {
+            for event in passthrough.fetch_events().unwrap() {
+                if let EventSummary::Key(_, code, _) = event.destructure() {
+                    seen.push(code);
+                }
+            }
+        }
+        assert!(!seen.contains(&KeyCode::KEY_P), "{:?}", seen);
+    }
+
+    #[test]
+    #[ignore = "requires access to /dev/uinput"]
+    fn passes_volume_keys_through_on_request() {
+        let (_keyboard, device) = virtual_keyboard();
+        let (_keys, sender) = start(vec![device], &HotkeyConfig::default()).unwrap();
+        let mut passthrough = passthrough_device();
+
+        sender.send(VolumeKey::Mute).unwrap();
+
+        let mut seen = Vec::new();
+        while seen.len() < 2 {
+            for event in passthrough.fetch_events().unwrap() {
+                if let EventSummary::Key(_, code, value) = event.destructure() {
+                    seen.push((code, value));
+                }
+            }
+        }
+        assert_eq!(seen, vec![(KeyCode::KEY_MUTE, 1), (KeyCode::KEY_MUTE, 0)]);
+    }
+}
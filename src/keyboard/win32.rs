@@ -0,0 +1,175 @@
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::ptr::null_mut;
+use std::ffi::c_void;
+use std::sync::Mutex;
+use windows::Win32::Foundation::*;
+use windows::Win32::UI::Input::KeyboardAndMouse::*;
+use windows::Win32::UI::WindowsAndMessaging::*;
+use crate::keyboard::Modifiers;
+use crate::volume::VolumeKey;
+use crate::worker::CommandSender;
+
+static HOOK_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+
+// Where the hook queues volume key presses; only held for a non-blocking send
+static COMMANDS: Mutex<Option<CommandSender>> = Mutex::new(None);
+
+// Virtual key codes for media keys - using u32 to match KBDLLHOOKSTRUCT.vkCode type
+const VK_VOLUME_MUTE: u32 = 0xAD;
+const VK_VOLUME_DOWN: u32 = 0xAE;
+const VK_VOLUME_UP: u32 = 0xAF;
+
+// Tags the presses `pass_through` injects, so the hook lets them go instead of queuing them again
+const PASSTHROUGH_MARKER: usize = 0x4657_5650;
+
+/// Maps a virtual key code to the volume key it represents
+pub fn volume_key(vk_code: u32) -> Option<VolumeKey> {
+    match vk_code {
+        VK_VOLUME_UP => Some(VolumeKey::Up),
+        VK_VOLUME_DOWN => Some(VolumeKey::Down),
+        VK_VOLUME_MUTE => Some(VolumeKey::Mute),
+        _ => None,
+    }
+}
+
+/// The volume key a virtual key code represents, with the modifier keys
+/// `held` reports as down. Modifiers other than Shift, Ctrl and Alt, like the
+/// Windows key, are ignored.
+pub fn classify(vk_code: u32, held: impl Fn(VIRTUAL_KEY) -> bool) -> Option<(VolumeKey, Modifiers)> {
+    let key = volume_key(vk_code)?;
+    let modifiers = Modifiers {
+        shift: held(VK_SHIFT),
+        ctrl: held(VK_CONTROL),
+        alt: held(VK_MENU),
+    };
+    Some((key, modifiers))
+}
+
+// Whether a key is down right now, as far as the hook can tell
+fn is_held(vk: VIRTUAL_KEY) -> bool {
+    unsafe { GetAsyncKeyState(vk.0 as i32) < 0 }
+}
+
+// Callback function for keyboard hook
+//
+// Windows silently removes low-level hooks that take longer than
+// LowLevelHooksTimeout, so this only classifies the key and queues it for the
+// worker thread.
+extern "system" fn keyboard_hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    unsafe {
+        // Keys pressed with Alt held arrive as WM_SYSKEYDOWN
+        let message = wparam.0 as u32;
+        let down = message == WM_KEYDOWN || message == WM_SYSKEYDOWN;
+        let up = message == WM_KEYUP || message == WM_SYSKEYUP;
+        if code >= 0 && (down || up) {
+            let kb_struct = *(lparam.0 as *const KBDLLHOOKSTRUCT);
+
+            // Check if it's a volume key event we didn't inject ourselves
+            if let Some((key, modifiers)) = classify(kb_struct.vkCode, is_held).filter(|_| kb_struct.dwExtraInfo != PASSTHROUGH_MARKER) {
+                // Releases time long presses of mute and stop a held key ramping
+                let queued = match &*COMMANDS.lock().unwrap() {
+                    Some(commands) if down => commands.send_with_modifiers(key, modifiers),
+                    Some(commands) => commands.release(key),
+                    None => false,
+                };
+                if queued {
+                    return LRESULT(1); // Prevent default behavior
+                }
+            }
+        }
+        
+        // Call the next hook in the chain for non-volume keys
+        CallNextHookEx(HHOOK(HOOK_HANDLE.load(Ordering::SeqCst) as isize), code, wparam, lparam)
+    }
+}
+
+/// Presses and releases a volume key the worker had nothing to do with, so
+/// the system handles it as if there were no hook
+pub fn pass_through(key: VolumeKey) {
+    let vk_code = match key {
+        VolumeKey::Up => VK_VOLUME_UP,
+        VolumeKey::Down => VK_VOLUME_DOWN,
+        VolumeKey::Mute => VK_VOLUME_MUTE,
+    };
+    let input = |flags: KEYBD_EVENT_FLAGS| INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: VIRTUAL_KEY(vk_code as u16),
+                wScan: 0,
+                dwFlags: KEYEVENTF_EXTENDEDKEY | flags,
+                time: 0,
+                dwExtraInfo: PASSTHROUGH_MARKER,
+            },
+        },
+    };
+    let inputs = [input(KEYBD_EVENT_FLAGS(0)), input(KEYEVENTF_KEYUP)];
+
+    unsafe {
+        if SendInput(&inputs, std::mem::size_of::<INPUT>() as i32) != inputs.len() as u32 {
+            println!("Error passing key through: {}", windows::core::Error::from_win32());
+        }
+    }
+}
+
+/// Installs the hook, sending volume key presses to `commands`
+pub fn install_keyboard_hook(commands: CommandSender) -> Result<(), Box<dyn std::error::Error>> {
+    *COMMANDS.lock().unwrap() = Some(commands);
+
+    unsafe {
+        let hook = SetWindowsHookExW(
+            WH_KEYBOARD_LL,
+            Some(keyboard_hook_proc),
+            HINSTANCE(0),
+            0,
+        )?;
+        
+        // Store the hook handle
+        HOOK_HANDLE.store(hook.0 as *mut c_void, Ordering::SeqCst);
+        println!("Keyboard hook installed successfully. Listening for volume keys...");
+        
+        Ok(())
+    }
+}
+
+pub fn uninstall_keyboard_hook() -> Result<(), Box<dyn std::error::Error>> {
+    let hook_ptr = HOOK_HANDLE.load(Ordering::SeqCst);
+    if !hook_ptr.is_null() {
+        unsafe {
+            UnhookWindowsHookEx(HHOOK(hook_ptr as isize))?;
+            HOOK_HANDLE.store(null_mut(), Ordering::SeqCst);
+            println!("Keyboard hook uninstalled");
+        }
+    }
+    *COMMANDS.lock().unwrap() = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify_with(vk_code: u32, keys: &[VIRTUAL_KEY]) -> Option<(VolumeKey, Modifiers)> {
+        classify(vk_code, |vk| keys.contains(&vk))
+    }
+
+    #[test]
+    fn classifies_volume_keys_with_their_modifiers() {
+        assert_eq!(classify_with(VK_VOLUME_UP, &[]), Some((VolumeKey::Up, Modifiers::NONE)));
+        assert_eq!(classify_with(VK_VOLUME_DOWN, &[VK_SHIFT]), Some((VolumeKey::Down, Modifiers { shift: true, ..Modifiers::NONE })));
+        assert_eq!(
+            classify_with(VK_VOLUME_MUTE, &[VK_CONTROL, VK_MENU]),
+            Some((VolumeKey::Mute, Modifiers { ctrl: true, alt: true, ..Modifiers::NONE })),
+        );
+    }
+
+    #[test]
+    fn ignores_other_keys_and_modifiers() {
+        assert_eq!(classify_with(VK_A.0 as u32, &[VK_SHIFT]), None);
+        assert_eq!(classify_with(VK_MEDIA_PLAY_PAUSE.0 as u32, &[]), None);
+
+        // The Windows key is not a modifier chords can use
+        assert_eq!(classify_with(VK_VOLUME_UP, &[VK_LWIN]), Some((VolumeKey::Up, Modifiers::NONE)));
+        assert_eq!(classify_with(VK_VOLUME_UP, &[VK_RWIN, VK_CONTROL]), Some((VolumeKey::Up, Modifiers { ctrl: true, ..Modifiers::NONE })));
+    }
+}
@@ -0,0 +1,26 @@
+//! Volume engine for redirecting media key volume adjustments to the focused
+//! application.
+//!
+//! The engine itself (`volume`, and the backend traits in `audio` and
+//! `focus`) has no OS dependencies. Platform glue is compiled in with cargo
+//! features: `win32` for Windows, and `pulse`, `x11` and `evdev` for Linux.
+
+pub mod acceleration;
+pub mod audio;
+pub mod clock;
+pub mod config;
+pub mod controller;
+pub mod error;
+pub mod focus;
+pub mod history;
+pub mod keyboard;
+pub mod matcher;
+pub mod process;
+pub mod scale;
+pub mod target;
+#[cfg(all(windows, feature = "win32"))]
+pub mod tray;
+pub mod volume;
+pub mod worker;
+
+pub use error::{Error, Result};
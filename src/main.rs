@@ -1,16 +1,23 @@
 #![windows_subsystem = "windows"]
 
-mod tray;
-mod keyboard;
-mod focus;
-mod audio;
-
+#[cfg(all(windows, feature = "win32"))]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use focused_window_volume::audio::WasapiBackend;
+    use focused_window_volume::focus::Win32FocusProvider;
+    use focused_window_volume::worker::Worker;
+    use focused_window_volume::{keyboard, tray};
+
+    let config = load_config();
+    let hotkeys = config.hotkeys.clone();
+
+    // Volume changes happen on a worker thread, away from the keyboard hook
+    let worker = Worker::spawn(Win32FocusProvider, WasapiBackend::new, config, keyboard::pass_through);
+
     // Install keyboard hook to capture volume keys
-    keyboard::install_keyboard_hook()?;
+    keyboard::install_keyboard_hook(worker.sender())?;
     
     // Set up system tray
-    let tray = tray::Tray::new("Focused Window Volume")?;
+    let tray = tray::Tray::new("Focused Window Volume", worker.sender(), &hotkeys)?;
     println!("Tray application started. Check your system tray!");
     
     // Run the message loop - this keeps the application running
@@ -18,7 +25,44 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Cleanup keyboard hook before exiting
     keyboard::uninstall_keyboard_hook()?;
+    worker.shutdown();
     
     // Return any result from the tray
     result
 }
+
+#[cfg(all(target_os = "linux", feature = "pulse", feature = "x11", feature = "evdev"))]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use focused_window_volume::audio::PulseBackend;
+    use focused_window_volume::focus::X11FocusProvider;
+    use focused_window_volume::keyboard;
+
+    let config = load_config();
+    let provider = X11FocusProvider::new()?;
+    let backend = PulseBackend::new()?;
+
+    // Runs until the input devices go away
+    keyboard::evdev::run_input_source(&provider, &backend, &config)?;
+    Ok(())
+}
+
+// A broken config file shouldn't stop the volume keys from working
+#[cfg(any(
+    all(windows, feature = "win32"),
+    all(target_os = "linux", feature = "pulse", feature = "x11", feature = "evdev"),
+))]
+fn load_config() -> focused_window_volume::config::Config {
+    focused_window_volume::config::Config::load().unwrap_or_else(|e| {
+        println!("Error loading config, using defaults: {}", e);
+        Default::default()
+    })
+}
+
+#[cfg(not(any(
+    all(windows, feature = "win32"),
+    all(target_os = "linux", feature = "pulse", feature = "x11", feature = "evdev"),
+)))]
+fn main() {
+    eprintln!("focused-window-volume was built without platform support. Enable the `win32` feature on Windows or the `linux` feature on Linux.");
+    std::process::exit(1);
+}
@@ -0,0 +1,502 @@
+pub mod alias;
+
+use serde::Deserialize;
+use crate::audio::{AudioBackend, SessionRegistry};
+use crate::error::{Error, Result};
+use crate::focus::FocusedWindow;
+use crate::process::{CachedProcesses, ProcessSource, ProcessTable, SystemProcesses, SNAPSHOT_TTL};
+use alias::AliasRule;
+
+/// The strategy that matched sessions to a window, from most to least specific
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchTier {
+    /// A user-defined alias rule picked the sessions
+    Alias,
+    /// The sessions belong to the process that owns the window
+    Pid,
+    /// The sessions belong to descendants of that process, all running the same application
+    ProcessTree,
+    /// The sessions belong to processes running the window's executable
+    Path,
+    /// Only the executable file names are the same, e.g. for sandboxed apps
+    FileName,
+    /// The sessions belong to the nearest ancestor of that process that has any
+    Ancestor,
+}
+
+/// Desktop shells and app launchers, which start nearly everything, so their
+/// descendants and their own sessions say nothing about the window
+const DESKTOP_SHELLS: &[&str] = &[
+    "explorer.exe",
+    "gnome-shell",
+    "plasmashell",
+    "krunner",
+    "cinnamon",
+    "mate-panel",
+    "xfce4-panel",
+    "xfdesktop",
+    "systemd",
+    "init",
+];
+
+fn is_desktop_shell(table: &ProcessTable, pid: u32) -> bool {
+    table.name(pid).is_some_and(|name| DESKTOP_SHELLS.iter().any(|shell| shell.eq_ignore_ascii_case(name)))
+}
+
+/// Settings for a `SessionMatcher`, read from the `[matching]` table of the config file
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatcherConfig {
+    /// How many generations of children, and of parents, the process tree
+    /// tiers look through. Zero turns them off.
+    pub max_process_depth: usize,
+    /// Rules tried before any other tier, in order
+    pub aliases: Vec<AliasRule>,
+}
+
+impl Default for MatcherConfig {
+    fn default() -> Self {
+        // Enough for terminal -> shell -> player and launcher -> wrapper -> game
+        Self { max_process_depth: 3, aliases: Vec::new() }
+    }
+}
+
+/// The sessions that play a window's audio, and how they were found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionMatch<S> {
+    pub tier: MatchTier,
+    pub sessions: Vec<S>,
+}
+
+/// Finds the sessions belonging to a window.
+///
+/// Tiers are tried in order and the first one that finds anything wins, so
+/// two instances of the same program are told apart by PID, while launchers
+/// and multi-process apps fall through to the process tree or executable.
+/// Alias rules come first, since they are how users correct the other tiers.
+pub struct SessionMatcher<S> {
+    config: MatcherConfig,
+    registry: SessionRegistry<S>,
+    processes: Box<dyn ProcessSource>,
+}
+
+impl<S: Clone + PartialEq> SessionMatcher<S> {
+    /// Creates a matcher that walks the processes running on this machine,
+    /// reading them at most once every `SNAPSHOT_TTL`
+    pub fn new(config: MatcherConfig) -> Self {
+        Self::with_processes(config, CachedProcesses::new(SystemProcesses, SNAPSHOT_TTL))
+    }
+
+    /// Creates a matcher that walks the process tree snapshots taken by `processes`
+    pub fn with_processes(config: MatcherConfig, processes: impl ProcessSource + 'static) -> Self {
+        Self {
+            config,
+            registry: SessionRegistry::new(),
+            processes: Box::new(processes),
+        }
+    }
+
+    /// Rescans the sessions on the next lookup, after one of them turned out to be gone
+    pub fn mark_stale(&mut self) {
+        self.registry.mark_stale();
+    }
+
+    /// Finds the sessions that play the window's audio
+    pub fn find_sessions<B: AudioBackend<Session = S>>(&mut self, backend: &B, window: &FocusedWindow) -> Result<SessionMatch<S>> {
+        let tiers = [MatchTier::Alias, MatchTier::Pid, MatchTier::ProcessTree, MatchTier::Path, MatchTier::FileName, MatchTier::Ancestor];
+        self.find_in_tiers(backend, window, &tiers)
+    }
+
+    /// Finds the sessions of whatever process runs the executable at `path`,
+    /// by path or else by file name, for applications chosen by the user
+    /// rather than by focus
+    pub fn find_sessions_for_path<B: AudioBackend<Session = S>>(&mut self, backend: &B, path: &str) -> Result<SessionMatch<S>> {
+        let window = FocusedWindow { path: path.to_string(), ..Default::default() };
+        self.find_in_tiers(backend, &window, &[MatchTier::Path, MatchTier::FileName])
+    }
+
+    /// Every session there is, in the order they were registered
+    pub fn all_sessions<B: AudioBackend<Session = S>>(&mut self, backend: &B) -> Result<Vec<S>> {
+        self.registry.sync(backend)?;
+        Ok(self.registry.iter().map(|registered| registered.session.clone()).collect())
+    }
+
+    /// The executable path of the process that owns `session`, if it is known
+    pub fn session_path(&self, session: &S) -> Option<String> {
+        self.registry.iter()
+            .find(|registered| registered.session == session)
+            .and_then(|registered| registered.path.map(str::to_string))
+    }
+
+    fn find_in_tiers<B: AudioBackend<Session = S>>(&mut self, backend: &B, window: &FocusedWindow, tiers: &[MatchTier]) -> Result<SessionMatch<S>> {
+        self.registry.sync(backend)?;
+
+        for &tier in tiers {
+            let sessions = self.sessions_for_tier(tier, window);
+            if !sessions.is_empty() {
+                return Ok(SessionMatch { tier, sessions });
+            }
+        }
+
+        Err(Error::SessionNotFound { path: window.path.clone() })
+    }
+
+    /// Finds the sessions of the first application that is making sound.
+    ///
+    /// Every session of that application is included, playing or not, so it
+    /// is adjusted as a whole like a focused one would be.
+    pub fn playing_sessions<B: AudioBackend<Session = S>>(&mut self, backend: &B) -> Result<Vec<S>> {
+        self.registry.sync(backend)?;
+
+        for session in self.registry.iter() {
+            match backend.is_playing(session.session) {
+                Ok(true) => {}
+                Ok(false) | Err(Error::SessionExpired) => continue,
+                Err(e) => return Err(e),
+            }
+
+            let application = match session.path {
+                Some(path) => self.registry.sessions_for_path(path),
+                None => self.registry.sessions_for_pid(session.pid),
+            };
+            return Ok(application.to_vec());
+        }
+
+        Ok(Vec::new())
+    }
+
+    fn sessions_for_tier(&self, tier: MatchTier, window: &FocusedWindow) -> Vec<S> {
+        match tier {
+            MatchTier::Alias => self.alias_sessions(window),
+            MatchTier::Pid => self.registry.sessions_for_pid(window.pid).to_vec(),
+            MatchTier::ProcessTree => self.descendant_sessions(window.pid),
+            MatchTier::Path => self.registry.sessions_for_path(&window.path).to_vec(),
+            MatchTier::FileName => self.registry.sessions_for_file_name(&window.path).to_vec(),
+            MatchTier::Ancestor => self.ancestor_sessions(window.pid),
+        }
+    }
+
+    fn alias_sessions(&self, window: &FocusedWindow) -> Vec<S> {
+        let rules: Vec<&AliasRule> = self.config.aliases.iter().filter(|rule| rule.applies_to(window)).collect();
+        if rules.is_empty() {
+            return Vec::new();
+        }
+
+        // Only rules about parent processes are worth a snapshot
+        let table = if rules.iter().any(|rule| rule.needs_parent()) {
+            self.snapshot().unwrap_or_default()
+        } else {
+            ProcessTable::new()
+        };
+        let parent_name = |pid| table.parent(pid).and_then(|parent| table.name(parent));
+
+        // The first rule that targets anything wins; if none do, the other tiers get a go
+        rules.into_iter()
+            .map(|rule| {
+                self.registry.iter()
+                    .filter(|session| rule.targets(session, parent_name(session.pid)))
+                    .map(|session| session.session.clone())
+                    .collect::<Vec<S>>()
+            })
+            .find(|sessions| !sessions.is_empty())
+            .unwrap_or_default()
+    }
+
+    // Terminals and launchers play through their children. A shell running
+    // several different programs doesn't say which one the keys are for, though.
+    fn descendant_sessions(&self, pid: u32) -> Vec<S> {
+        let Some(table) = self.process_table() else {
+            return Vec::new();
+        };
+        if is_desktop_shell(&table, pid) {
+            return Vec::new();
+        }
+
+        let owners: Vec<u32> = table.descendants(pid, self.config.max_process_depth)
+            .into_iter()
+            .filter(|&owner| !self.registry.sessions_for_pid(owner).is_empty())
+            .collect();
+        let application = |owner| self.registry.iter().find(|session| session.pid == owner).and_then(|session| session.path);
+        let single = match owners.as_slice() {
+            [] => false,
+            [_] => true,
+            [first, rest @ ..] => application(*first).is_some_and(|path| rest.iter().all(|&owner| application(owner) == Some(path))),
+        };
+        if !single {
+            return Vec::new();
+        }
+
+        owners.into_iter()
+            .flat_map(|owner| self.registry.sessions_for_pid(owner).to_vec())
+            .collect()
+    }
+
+    // The window may belong to a child of the process playing audio; take the
+    // nearest one, short of the desktop shell that started everything
+    fn ancestor_sessions(&self, pid: u32) -> Vec<S> {
+        let Some(table) = self.process_table() else {
+            return Vec::new();
+        };
+
+        table.ancestors(pid, self.config.max_process_depth)
+            .into_iter()
+            .take_while(|&ancestor| !is_desktop_shell(&table, ancestor))
+            .map(|ancestor| self.registry.sessions_for_pid(ancestor))
+            .find(|sessions| !sessions.is_empty())
+            .map(<[S]>::to_vec)
+            .unwrap_or_default()
+    }
+
+    // A snapshot for the process tree tiers, unless they can't find anything
+    fn process_table(&self) -> Option<ProcessTable> {
+        // By these tiers no session belongs to the window's own process, so without any there is nothing to find
+        if self.config.max_process_depth == 0 || self.registry.is_empty() {
+            return None;
+        }
+        self.snapshot()
+    }
+
+    // Failing to read the process tree only rules out the tiers that need it
+    fn snapshot(&self) -> Option<ProcessTable> {
+        match self.processes.snapshot() {
+            Ok(table) => Some(table),
+            Err(e) => {
+                println!("Error reading process tree: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl<S: Clone + PartialEq> Default for SessionMatcher<S> {
+    fn default() -> Self {
+        Self::new(MatcherConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::audio::{MockAudioBackend, MockSession};
+
+    fn window(pid: u32, path: &str) -> FocusedWindow {
+        FocusedWindow {
+            pid,
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn find(matcher: &mut SessionMatcher<MockSession>, backend: &MockAudioBackend, pid: u32, path: &str) -> SessionMatch<MockSession> {
+        matcher.find_sessions(backend, &window(pid, path)).unwrap()
+    }
+
+    #[test]
+    fn exact_pid_tells_instances_of_the_same_program_apart() {
+        let backend = MockAudioBackend::new();
+        let first = backend.add_session(10, "/usr/bin/vlc");
+        let second = backend.add_session(11, "/usr/bin/vlc");
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), ProcessTable::new());
+
+        let found = find(&mut matcher, &backend, 11, "/usr/bin/vlc");
+        assert_eq!(found, SessionMatch { tier: MatchTier::Pid, sessions: vec![second] });
+        assert_eq!(find(&mut matcher, &backend, 10, "/usr/bin/vlc").sessions, vec![first]);
+    }
+
+    #[test]
+    fn descendants_of_terminals_and_launchers_are_matched() {
+        // terminal(100) -> shell(101) -> mpv(102), launcher(300) -> { game(301), game helper(302) }
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(102, "/usr/bin/mpv");
+        let game = backend.add_session(301, "/games/game");
+        let helper = backend.add_session(302, "/games/game");
+        backend.add_session(200, "/usr/bin/firefox");
+        let processes = ProcessTable::from_parents([(100, 1), (101, 100), (102, 101), (200, 1), (300, 1), (301, 300), (302, 301)]);
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), processes);
+
+        let found = find(&mut matcher, &backend, 100, "/usr/bin/alacritty");
+        assert_eq!(found, SessionMatch { tier: MatchTier::ProcessTree, sessions: vec![mpv] });
+        assert_eq!(find(&mut matcher, &backend, 300, "/usr/bin/launcher").sessions, vec![game, helper]);
+    }
+
+    #[test]
+    fn shells_running_several_applications_are_not_matched() {
+        // terminal(100) -> shell(101) -> { mpv(102), cmus(103) }
+        let backend = MockAudioBackend::new();
+        backend.add_session(102, "/usr/bin/mpv");
+        backend.add_session(103, "/usr/bin/cmus");
+        let processes = ProcessTable::from_parents([(100, 1), (101, 100), (102, 101), (103, 101)]);
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), processes);
+
+        assert!(matcher.find_sessions(&backend, &window(100, "/usr/bin/alacritty")).is_err());
+        assert!(matcher.find_sessions(&backend, &window(101, "/usr/bin/bash")).is_err());
+    }
+
+    #[test]
+    fn desktop_shells_are_left_out_of_the_process_tree() {
+        // explorer(10) -> spotify(11), gnome-shell(20, playing) -> gedit(21)
+        let backend = MockAudioBackend::new();
+        backend.add_session(11, "C:\\Spotify\\Spotify.exe");
+        backend.add_session(20, "/usr/bin/gnome-shell");
+        let mut processes = ProcessTable::from_parents([(10, 4), (11, 10), (20, 1), (21, 20)]);
+        processes.set_name(10, "explorer.exe");
+        processes.set_name(20, "gnome-shell");
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), processes);
+
+        assert!(matcher.find_sessions(&backend, &window(10, "C:\\Windows\\explorer.exe")).is_err());
+        assert!(matcher.find_sessions(&backend, &window(21, "/usr/bin/gedit")).is_err());
+    }
+
+    #[test]
+    fn ancestors_are_only_matched_when_nothing_else_is() {
+        // steam(300) -> { game(301), other game(302) }
+        let backend = MockAudioBackend::new();
+        let steam = backend.add_session(300, "/usr/bin/steam");
+        let game = backend.add_session(400, "/games/game");
+        let processes = ProcessTable::from_parents([(300, 1), (301, 300), (302, 300), (400, 1)]);
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), processes);
+
+        // Another instance of the game is playing, so the launcher's sessions aren't taken
+        assert_eq!(find(&mut matcher, &backend, 301, "/games/game"), SessionMatch { tier: MatchTier::Path, sessions: vec![game] });
+        assert_eq!(find(&mut matcher, &backend, 302, "/games/other"), SessionMatch { tier: MatchTier::Ancestor, sessions: vec![steam] });
+    }
+
+    // Counts the snapshots it is asked for
+    struct CountingSource(Arc<AtomicUsize>);
+
+    impl ProcessSource for CountingSource {
+        fn snapshot(&self) -> Result<ProcessTable> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(ProcessTable::from_parents([(200, 1), (201, 200)]))
+        }
+    }
+
+    #[test]
+    fn process_snapshots_are_only_taken_when_they_can_help() {
+        let backend = MockAudioBackend::new();
+        let count = Arc::new(AtomicUsize::new(0));
+        let processes = CachedProcesses::new(CountingSource(count.clone()), Duration::from_secs(3600));
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), processes);
+
+        // No sessions at all, so no process could own one
+        assert!(matcher.find_sessions(&backend, &window(201, "/usr/lib/firefox/firefox")).is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        // A plugin's window belongs to a process without a session, on every press
+        let firefox = backend.add_session(200, "/usr/lib/firefox/firefox");
+        for _ in 0..5 {
+            assert_eq!(find(&mut matcher, &backend, 201, "/usr/lib/firefox/plugin-container").sessions, vec![firefox]);
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1, "presses in quick succession should share a snapshot");
+    }
+
+    #[test]
+    fn process_tree_depth_is_limited() {
+        // terminal(100) -> shell(101) -> tmux(102) -> mpv(103)
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(103, "/usr/bin/mpv");
+        let processes = ProcessTable::from_parents([(100, 1), (101, 100), (102, 101), (103, 102)]);
+
+        let shallow = MatcherConfig { max_process_depth: 2, ..Default::default() };
+        let mut matcher = SessionMatcher::with_processes(shallow, processes.clone());
+        assert!(matcher.find_sessions(&backend, &window(100, "/usr/bin/alacritty")).is_err());
+
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), processes.clone());
+        assert_eq!(find(&mut matcher, &backend, 100, "/usr/bin/alacritty").sessions, vec![mpv]);
+
+        let disabled = MatcherConfig { max_process_depth: 0, ..Default::default() };
+        let mut matcher = SessionMatcher::with_processes(disabled, processes);
+        assert!(matcher.find_sessions(&backend, &window(103, "/usr/bin/other")).is_ok(), "exact PIDs match regardless");
+        assert!(matcher.find_sessions(&backend, &window(102, "/usr/bin/tmux")).is_err());
+    }
+
+    #[test]
+    fn nearest_ancestor_is_matched_when_no_descendant_plays() {
+        // launcher(300, playing) -> wrapper(301, playing) -> game window(302)
+        let backend = MockAudioBackend::new();
+        backend.add_session(300, "C:\\Launcher\\Launcher.exe");
+        let wrapper = backend.add_session(301, "C:\\Launcher\\Wrapper.exe");
+        let processes = ProcessTable::from_parents([(300, 4), (301, 300), (302, 301)]);
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), processes);
+
+        let found = find(&mut matcher, &backend, 302, "C:\\Games\\Game.exe");
+        assert_eq!(found, SessionMatch { tier: MatchTier::Ancestor, sessions: vec![wrapper] });
+    }
+
+    #[test]
+    fn falls_back_to_executable_path_then_file_name() {
+        let backend = MockAudioBackend::new();
+        let renderer = backend.add_session(401, "/usr/lib/chromium/chromium");
+        let flatpak = backend.add_session(7, "spotify");
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), ProcessTable::new());
+
+        let found = find(&mut matcher, &backend, 400, "/usr/lib/chromium/chromium");
+        assert_eq!(found, SessionMatch { tier: MatchTier::Path, sessions: vec![renderer] });
+
+        let found = find(&mut matcher, &backend, 500, "/app/extra/share/spotify/Spotify");
+        assert_eq!(found, SessionMatch { tier: MatchTier::FileName, sessions: vec![flatpak] });
+    }
+
+    #[test]
+    fn alias_rules_come_before_other_tiers() {
+        // Discord's window belongs to app-1.0\Discord.exe(20), but the voice chat plays
+        // from a helper(21) that Update.exe(10) started, and a stray session plays at pid 20
+        let backend = MockAudioBackend::new();
+        backend.add_session(20, "C:\\Discord\\app-1.0\\Discord.exe");
+        let voice = backend.add_session(21, "C:\\Discord\\app-1.0\\modules\\voice.exe");
+        let mut processes = ProcessTable::from_parents([(10, 4), (20, 10), (21, 10)]);
+        processes.set_name(10, "Update.exe");
+
+        let config = MatcherConfig {
+            aliases: vec![AliasRule {
+                when_executable: Some(alias::Pattern::new("*\\Discord.exe").unwrap()),
+                target_parent: Some(alias::Pattern::new("update.exe").unwrap()),
+                target_path: Some(alias::Pattern::new("re:voice").unwrap()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut matcher = SessionMatcher::with_processes(config, processes);
+
+        let found = find(&mut matcher, &backend, 20, "C:\\Discord\\app-1.0\\Discord.exe");
+        assert_eq!(found, SessionMatch { tier: MatchTier::Alias, sessions: vec![voice] });
+    }
+
+    #[test]
+    fn alias_rules_without_targets_fall_through() {
+        let backend = MockAudioBackend::new();
+        let vlc = backend.add_session(10, "/usr/bin/vlc");
+        let config = MatcherConfig {
+            aliases: vec![AliasRule {
+                when_class: Some(alias::Pattern::new("vlc").unwrap()),
+                target_name: Some(alias::Pattern::new("VLC media player").unwrap()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut matcher = SessionMatcher::with_processes(config, ProcessTable::new());
+
+        let window = FocusedWindow { pid: 10, path: "/usr/bin/vlc".to_string(), class: "vlc".to_string(), ..Default::default() };
+        let found = matcher.find_sessions(&backend, &window).unwrap();
+        assert_eq!(found, SessionMatch { tier: MatchTier::Pid, sessions: vec![vlc] });
+
+        backend.set_name_of(vlc, "VLC media player");
+        matcher.mark_stale();
+        assert_eq!(matcher.find_sessions(&backend, &window).unwrap().tier, MatchTier::Alias);
+    }
+
+    #[test]
+    fn reports_windows_without_any_match() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(10, "/usr/bin/vlc");
+        let mut matcher = SessionMatcher::with_processes(MatcherConfig::default(), ProcessTable::from_parents([(10, 1), (20, 1)]));
+
+        assert!(matches!(
+            matcher.find_sessions(&backend, &window(20, "/usr/bin/gedit")),
+            Err(Error::SessionNotFound { path }) if path == "/usr/bin/gedit"
+        ));
+    }
+}
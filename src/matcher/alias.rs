@@ -0,0 +1,247 @@
+use regex::Regex;
+use serde::Deserialize;
+use crate::audio::RegisteredSession;
+use crate::error::{Error, Result};
+use crate::focus::FocusedWindow;
+
+/// A pattern for a path, window class or name.
+///
+/// Patterns starting with `re:` are regular expressions that may match
+/// anywhere in the text. Anything else is a case-insensitive glob matched
+/// against the whole text, where `*` matches any run of characters
+/// (including path separators) and `?` matches a single one.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let regex = match pattern.strip_prefix("re:") {
+            Some(expression) => Regex::new(expression),
+            None => Regex::new(&glob_to_regex(pattern)),
+        };
+
+        match regex {
+            Ok(regex) => Ok(Self { source: pattern.to_string(), regex }),
+            Err(e) => Err(Error::InvalidConfig(format!("Invalid pattern {:?}: {}", pattern, e))),
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = Error;
+
+    fn try_from(pattern: String) -> Result<Self> {
+        Pattern::new(&pattern)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Pattern {}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("(?i)^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Sends the volume keys of one application to the sessions of another.
+///
+/// A rule applies when every `when_*` pattern it has matches the focused
+/// window, and then targets the sessions that match every `target_*` pattern
+/// it has. At least one of each is required.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AliasRule {
+    /// Matched against the focused window's executable path
+    pub when_executable: Option<Pattern>,
+    /// Matched against the focused window's class
+    pub when_class: Option<Pattern>,
+    /// Matched against the executable path of the session's process
+    pub target_path: Option<Pattern>,
+    /// Matched against the application name the session reports
+    pub target_name: Option<Pattern>,
+    /// Matched against the executable name of the parent of the session's process
+    pub target_parent: Option<Pattern>,
+}
+
+impl AliasRule {
+    /// Checks that the rule has something to match on both sides
+    pub fn validate(&self) -> Result<()> {
+        if self.when_executable.is_none() && self.when_class.is_none() {
+            return Err(Error::InvalidConfig("Alias rule needs when_executable or when_class".to_string()));
+        }
+        if self.target_path.is_none() && self.target_name.is_none() && self.target_parent.is_none() {
+            return Err(Error::InvalidConfig("Alias rule needs target_path, target_name or target_parent".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn applies_to(&self, window: &FocusedWindow) -> bool {
+        window_matches(&self.when_executable, &self.when_class, window)
+    }
+
+    /// Checks a session against the targets. `parent_name` is the executable
+    /// name of the session process's parent, if it is known.
+    pub fn targets<S>(&self, session: &RegisteredSession<'_, S>, parent_name: Option<&str>) -> bool {
+        matches(&self.target_path, session.path)
+            && matches(&self.target_name, session.name)
+            && matches(&self.target_parent, parent_name)
+    }
+
+    /// Whether `targets` needs to know the session's parent process
+    pub fn needs_parent(&self) -> bool {
+        self.target_parent.is_some()
+    }
+}
+
+/// Checks a window against optional executable path and class patterns, which both have to match
+pub fn window_matches(executable: &Option<Pattern>, class: &Option<Pattern>, window: &FocusedWindow) -> bool {
+    matches(executable, Some(&window.path)) && matches(class, Some(&window.class))
+}
+
+// A missing pattern matches anything, a missing value matches nothing
+fn matches(pattern: &Option<Pattern>, value: Option<&str>) -> bool {
+    match pattern {
+        Some(pattern) => value.is_some_and(|value| pattern.matches(value)),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(source: &str) -> Option<Pattern> {
+        Some(Pattern::new(source).unwrap())
+    }
+
+    #[test]
+    fn matches_globs_and_regular_expressions() {
+        let cases = [
+            ("*\\Discord.exe", "C:\\Users\\me\\AppData\\Local\\Discord\\app-1.0.9\\Discord.exe", true),
+            ("*\\discord.EXE", "C:\\Discord\\Discord.exe", true),
+            ("*\\Discord.exe", "C:\\Discord\\Discord.exe.bak", false),
+            ("steam_app_???", "steam_app_570", true),
+            ("steam_app_???", "steam_app_1091500", false),
+            ("/usr/bin/[mpv]", "/usr/bin/[mpv]", true),
+            ("re:^steam_app_\\d+$", "steam_app_1091500", true),
+            ("re:Helper", "/opt/Slack/Slack Helper (Renderer)", true),
+            ("re:^helper", "Helper", false),
+        ];
+
+        for (source, text, expected) in cases {
+            assert_eq!(Pattern::new(source).unwrap().matches(text), expected, "{:?} against {:?}", source, text);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_regular_expressions() {
+        assert!(matches!(Pattern::new("re:(unclosed"), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn rules_apply_to_focused_executable_and_class() {
+        let discord = AliasRule {
+            when_executable: pattern("*\\Discord.exe"),
+            target_parent: pattern("Update.exe"),
+            ..Default::default()
+        };
+        let steam_game = AliasRule {
+            when_class: pattern("re:^steam_app_\\d+$"),
+            when_executable: pattern("*/proton"),
+            target_name: pattern("*"),
+            ..Default::default()
+        };
+
+        let window = |path: &str, class: &str| FocusedWindow {
+            path: path.to_string(),
+            class: class.to_string(),
+            ..Default::default()
+        };
+        let cases = [
+            (&discord, window("C:\\Discord\\app-1.0\\Discord.exe", "Chrome_WidgetWin_1"), true),
+            (&discord, window("C:\\Slack\\slack.exe", "Chrome_WidgetWin_1"), false),
+            (&steam_game, window("/steam/common/Proton/proton", "steam_app_570"), true),
+            (&steam_game, window("/steam/common/Proton/proton", "Steam"), false),
+            (&steam_game, window("/usr/bin/wine", "steam_app_570"), false),
+        ];
+
+        for (i, (rule, window, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(rule.applies_to(&window), expected, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn rules_target_sessions_by_path_name_and_parent() {
+        let by_path = AliasRule { target_path: pattern("*\\app-*\\Discord.exe"), ..Default::default() };
+        let by_name = AliasRule { target_name: pattern("re:(?i)spotify"), ..Default::default() };
+        let by_parent = AliasRule { target_parent: pattern("Update.exe"), ..Default::default() };
+        let by_path_and_parent = AliasRule {
+            target_path: pattern("*\\Discord.exe"),
+            target_parent: pattern("Update.exe"),
+            ..Default::default()
+        };
+
+        let session = |path: Option<&'static str>, name: Option<&'static str>| RegisteredSession {
+            session: &(),
+            pid: 42,
+            path,
+            name,
+        };
+        let discord = session(Some("C:\\Discord\\app-1.0\\Discord.exe"), None);
+        let spotify = session(Some("C:\\Spotify\\Spotify.exe"), Some("Spotify"));
+        let elevated = session(None, Some("Tool"));
+
+        let cases = [
+            (&by_path, discord, None, true),
+            (&by_path, spotify, None, false),
+            (&by_path, elevated, None, false),
+            (&by_name, spotify, None, true),
+            (&by_name, discord, None, false),
+            (&by_parent, discord, Some("Update.exe"), true),
+            (&by_parent, discord, Some("explorer.exe"), false),
+            (&by_parent, discord, None, false),
+            (&by_path_and_parent, discord, Some("Update.exe"), true),
+            (&by_path_and_parent, spotify, Some("Update.exe"), false),
+        ];
+
+        for (i, (rule, session, parent, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(rule.targets(&session, parent), expected, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn rules_need_both_sides() {
+        let target_only = AliasRule { target_name: pattern("*"), ..Default::default() };
+        let when_only = AliasRule { when_class: pattern("*"), ..Default::default() };
+        let complete = AliasRule { when_class: pattern("*"), target_name: pattern("*"), ..Default::default() };
+
+        assert!(target_only.validate().is_err());
+        assert!(when_only.validate().is_err());
+        assert!(complete.validate().is_ok());
+    }
+}
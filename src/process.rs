@@ -0,0 +1,243 @@
+#[cfg(target_os = "linux")]
+pub mod linux;
+#[cfg(all(windows, feature = "win32"))]
+pub mod win32;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use crate::error::Result;
+
+/// How long `CachedProcesses` reuses a snapshot before taking a new one
+pub const SNAPSHOT_TTL: Duration = Duration::from_secs(1);
+
+/// Which process started which, at one point in time
+#[derive(Clone, Debug, Default)]
+pub struct ProcessTable {
+    parents: HashMap<u32, u32>,
+    children: HashMap<u32, Vec<u32>>,
+    names: HashMap<u32, String>,
+}
+
+impl ProcessTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a table from `(pid, parent pid)` pairs
+    pub fn from_parents(processes: impl IntoIterator<Item = (u32, u32)>) -> Self {
+        let mut table = Self::new();
+        for (pid, parent) in processes {
+            table.insert(pid, parent);
+        }
+        table
+    }
+
+    pub fn insert(&mut self, pid: u32, parent: u32) {
+        self.parents.insert(pid, parent);
+        self.children.entry(parent).or_default().push(pid);
+    }
+
+    /// Records the executable file name of a process
+    pub fn set_name(&mut self, pid: u32, name: &str) {
+        self.names.insert(pid, name.to_string());
+    }
+
+    /// Gets the executable file name of a process, if the source knows it
+    pub fn name(&self, pid: u32) -> Option<&str> {
+        self.names.get(&pid).map(String::as_str)
+    }
+
+    pub fn parent(&self, pid: u32) -> Option<u32> {
+        self.parents.get(&pid).copied()
+    }
+
+    pub fn children(&self, pid: u32) -> &[u32] {
+        self.children.get(&pid).map_or(&[], Vec::as_slice)
+    }
+
+    /// Processes started by `pid` directly or indirectly, nearest first.
+    ///
+    /// Children are at depth 1, grandchildren at depth 2 and so on, up to `max_depth`.
+    pub fn descendants(&self, pid: u32, max_depth: usize) -> Vec<u32> {
+        let mut descendants = Vec::new();
+        let mut seen = HashSet::from([pid]);
+        let mut queue = VecDeque::from([(pid, 0)]);
+
+        while let Some((current, depth)) = queue.pop_front() {
+            if depth == max_depth {
+                continue;
+            }
+            for &child in self.children(current) {
+                // Parent IDs can refer to a reused PID, which can make the table cyclic
+                if seen.insert(child) {
+                    descendants.push(child);
+                    queue.push_back((child, depth + 1));
+                }
+            }
+        }
+
+        descendants
+    }
+
+    /// Up to `max_depth` of the processes that started `pid`, nearest first,
+    /// without the idle process (PID 0)
+    pub fn ancestors(&self, pid: u32, max_depth: usize) -> Vec<u32> {
+        let mut ancestors = Vec::new();
+        let mut seen = HashSet::from([pid]);
+        let mut current = pid;
+
+        while let Some(parent) = self.parent(current) {
+            if ancestors.len() == max_depth || parent == 0 || !seen.insert(parent) {
+                break;
+            }
+            ancestors.push(parent);
+            current = parent;
+        }
+
+        ancestors
+    }
+}
+
+/// A way to take snapshots of the running processes
+pub trait ProcessSource {
+    fn snapshot(&self) -> Result<ProcessTable>;
+}
+
+// A fixed table stands in for the system in tests
+impl ProcessSource for ProcessTable {
+    fn snapshot(&self) -> Result<ProcessTable> {
+        Ok(self.clone())
+    }
+}
+
+/// Takes snapshots of the processes running on this machine
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemProcesses;
+
+impl ProcessSource for SystemProcesses {
+    fn snapshot(&self) -> Result<ProcessTable> {
+        system_snapshot()
+    }
+}
+
+#[cfg(target_os = "linux")]
+use linux::snapshot as system_snapshot;
+#[cfg(all(windows, feature = "win32"))]
+use win32::snapshot as system_snapshot;
+
+// Without a platform implementation the process tree tier never matches
+#[cfg(not(any(target_os = "linux", all(windows, feature = "win32"))))]
+fn system_snapshot() -> Result<ProcessTable> {
+    Ok(ProcessTable::new())
+}
+
+/// Reuses the last snapshot of another source for a short while.
+///
+/// Reading every process is slow, and a burst of key presses would
+/// otherwise take one per press while the tree hardly changes.
+pub struct CachedProcesses<P> {
+    source: P,
+    ttl: Duration,
+    cached: RefCell<Option<(Instant, ProcessTable)>>,
+}
+
+impl<P: ProcessSource> CachedProcesses<P> {
+    pub fn new(source: P, ttl: Duration) -> Self {
+        Self { source, ttl, cached: RefCell::new(None) }
+    }
+}
+
+impl<P: ProcessSource> ProcessSource for CachedProcesses<P> {
+    fn snapshot(&self) -> Result<ProcessTable> {
+        let now = Instant::now();
+        if let Some((taken, table)) = &*self.cached.borrow()
+            && now.duration_since(*taken) < self.ttl
+        {
+            return Ok(table.clone());
+        }
+
+        let table = self.source.snapshot()?;
+        *self.cached.borrow_mut() = Some((now, table.clone()));
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // systemd(1) -> terminal(100) -> bash(101) -> { mpv(102), cmus(103) -> helper(104) }
+    fn terminal_tree() -> ProcessTable {
+        ProcessTable::from_parents([(1, 0), (100, 1), (101, 100), (102, 101), (103, 101), (104, 103)])
+    }
+
+    #[test]
+    fn lists_descendants_nearest_first() {
+        let table = terminal_tree();
+        assert_eq!(table.descendants(100, 10), vec![101, 102, 103, 104]);
+        assert_eq!(table.descendants(102, 10), Vec::<u32>::new());
+        assert_eq!(table.children(101), &[102, 103]);
+    }
+
+    #[test]
+    fn limits_descendants_by_depth() {
+        let table = terminal_tree();
+        assert_eq!(table.descendants(100, 0), Vec::<u32>::new());
+        assert_eq!(table.descendants(100, 1), vec![101]);
+        assert_eq!(table.descendants(100, 2), vec![101, 102, 103]);
+    }
+
+    #[test]
+    fn lists_ancestors_nearest_first() {
+        let table = terminal_tree();
+        assert_eq!(table.ancestors(104, 10), vec![103, 101, 100, 1]);
+        assert_eq!(table.ancestors(104, 2), vec![103, 101]);
+        assert_eq!(table.ancestors(1, 10), Vec::<u32>::new());
+        assert_eq!(table.ancestors(999, 10), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn remembers_process_names() {
+        let mut table = terminal_tree();
+        table.set_name(102, "mpv");
+        assert_eq!(table.name(102), Some("mpv"));
+        assert_eq!(table.name(103), None);
+    }
+
+    #[test]
+    fn survives_cycles_from_reused_pids() {
+        let table = ProcessTable::from_parents([(10, 11), (11, 10)]);
+        assert_eq!(table.descendants(10, 10), vec![11]);
+        assert_eq!(table.ancestors(10, 10), vec![11]);
+    }
+
+    // Counts the snapshots it is asked for
+    struct CountingSource(Arc<AtomicUsize>);
+
+    impl ProcessSource for CountingSource {
+        fn snapshot(&self) -> Result<ProcessTable> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(terminal_tree())
+        }
+    }
+
+    #[test]
+    fn cached_snapshots_are_reused_until_they_expire() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let cached = CachedProcesses::new(CountingSource(count.clone()), Duration::from_secs(3600));
+        for _ in 0..5 {
+            assert_eq!(cached.snapshot().unwrap().parent(102), Some(101));
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let expired = CachedProcesses::new(CountingSource(count.clone()), Duration::ZERO);
+        for _ in 0..3 {
+            expired.snapshot().unwrap();
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::error::Result;
+use crate::process::ProcessTable;
+
+/// Reads the parent and name of every process from `/proc/<pid>/stat`
+pub fn snapshot() -> Result<ProcessTable> {
+    let mut table = ProcessTable::new();
+
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        if entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()).is_none() {
+            continue;
+        }
+
+        // Processes can exit while the directory is being read
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        if let Some((pid, parent, name)) = parse_stat(&stat) {
+            table.insert(pid, parent);
+            table.set_name(pid, name);
+        }
+    }
+
+    Ok(table)
+}
+
+/// Gets the pid, parent pid and command name out of the contents of a `/proc/<pid>/stat` file.
+///
+/// The kernel truncates command names to 15 bytes.
+pub fn parse_stat(stat: &str) -> Option<(u32, u32, &str)> {
+    // The command name is in parentheses and may itself contain spaces and parentheses
+    let (pid, rest) = stat.split_once(" (")?;
+    let (name, fields) = rest.rsplit_once(") ")?;
+
+    // After the name come the state and then the parent pid
+    let parent = fields.split_whitespace().nth(1)?;
+    Some((pid.trim().parse().ok()?, parent.parse().ok()?, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stat_lines() {
+        assert_eq!(parse_stat("1234 (mpv) S 1200 1234 1200 34817 1234 4194304"), Some((1234, 1200, "mpv")));
+        assert_eq!(parse_stat("77 (Web Content (1)) R 60 60 60 0 -1"), Some((77, 60, "Web Content (1)")));
+        assert_eq!(parse_stat("garbage"), None);
+    }
+
+    #[test]
+    fn finds_the_test_process_and_its_parent() {
+        let table = snapshot().unwrap();
+        assert_eq!(table.parent(std::process::id()), Some(std::os::unix::process::parent_id()));
+    }
+}
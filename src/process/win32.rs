@@ -0,0 +1,33 @@
+use windows::Win32::Foundation::CloseHandle;
+use windows::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+use crate::error::Result;
+use crate::process::ProcessTable;
+
+/// Reads the parent and executable name of every process from a Toolhelp32 snapshot
+pub fn snapshot() -> Result<ProcessTable> {
+    let mut table = ProcessTable::new();
+
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)?;
+
+        let mut entry = PROCESSENTRY32W {
+            dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+
+        // Process32NextW fails with ERROR_NO_MORE_FILES after the last process
+        let mut found = Process32FirstW(snapshot, &mut entry);
+        while found.is_ok() {
+            table.insert(entry.th32ProcessID, entry.th32ParentProcessID);
+
+            let len = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+            table.set_name(entry.th32ProcessID, &String::from_utf16_lossy(&entry.szExeFile[..len]));
+
+            found = Process32NextW(snapshot, &mut entry);
+        }
+
+        let _ = CloseHandle(snapshot);
+    }
+
+    Ok(table)
+}
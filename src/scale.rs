@@ -0,0 +1,135 @@
+use serde::Deserialize;
+use crate::error::{Error, Result};
+
+/// How volume steps are measured.
+///
+/// Backends take a linear scalar from 0.0 to 1.0, but loudness is heard
+/// roughly logarithmically, so equal linear steps make big changes near the
+/// bottom and barely audible ones near the top. The perceptual scales apply
+/// each step to a position from 0.0 to 1.0 along a taper, then convert the
+/// position back to the scalar the backend expects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VolumeScale {
+    /// Steps are added to the scalar as it is
+    #[default]
+    Linear,
+    /// The position is the cube root of the scalar
+    Cubic,
+    /// The position spans this many decibels below full volume; anything
+    /// quieter is silence
+    Decibels(f32),
+}
+
+impl VolumeScale {
+    /// Where a backend scalar sits on this scale
+    pub fn to_position(self, scalar: f32) -> f32 {
+        let scalar = scalar.clamp(0.0, 1.0);
+        match self {
+            VolumeScale::Linear => scalar,
+            VolumeScale::Cubic => scalar.cbrt(),
+            VolumeScale::Decibels(range_db) => {
+                if scalar <= 0.0 {
+                    return 0.0;
+                }
+                (1.0 + 20.0 * scalar.log10() / range_db).clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    /// The backend scalar for a position on this scale
+    pub fn to_scalar(self, position: f32) -> f32 {
+        let position = position.clamp(0.0, 1.0);
+        match self {
+            VolumeScale::Linear => position,
+            VolumeScale::Cubic => position.powi(3),
+            VolumeScale::Decibels(range_db) => {
+                if position <= 0.0 {
+                    return 0.0;
+                }
+                10f32.powf((position - 1.0) * range_db / 20.0)
+            }
+        }
+    }
+
+    /// Moves a backend scalar by `delta` along this scale, giving the new scalar
+    pub fn step(self, scalar: f32, delta: f32) -> f32 {
+        self.to_scalar(self.to_position(scalar) + delta)
+    }
+
+    pub fn validate(self) -> Result<()> {
+        match self {
+            VolumeScale::Decibels(range_db) if range_db <= 0.0 || !range_db.is_finite() => {
+                Err(Error::InvalidConfig(format!("Decibel range must be above 0, not {}", range_db)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCALES: [VolumeScale; 3] = [VolumeScale::Linear, VolumeScale::Cubic, VolumeScale::Decibels(60.0)];
+
+    fn samples() -> impl Iterator<Item = f32> {
+        (0..=100).map(|i| i as f32 / 100.0)
+    }
+
+    #[test]
+    fn positions_round_trip_through_scalars() {
+        for scale in SCALES {
+            for position in samples() {
+                let back = scale.to_position(scale.to_scalar(position));
+                assert!((back - position).abs() < 1e-4, "{:?}: {} came back as {}", scale, position, back);
+            }
+        }
+    }
+
+    #[test]
+    fn scalars_round_trip_through_positions() {
+        for scale in SCALES {
+            for scalar in samples() {
+                let back = scale.to_scalar(scale.to_position(scalar));
+                assert!((back - scalar).abs() < 1e-4, "{:?}: {} came back as {}", scale, scalar, back);
+            }
+        }
+
+        // Below the decibel range there is only silence
+        let decibels = VolumeScale::Decibels(40.0);
+        assert_eq!(decibels.to_scalar(decibels.to_position(0.001)), 0.0);
+    }
+
+    #[test]
+    fn scales_keep_the_ends_and_the_order() {
+        for scale in SCALES {
+            assert_eq!(scale.to_position(0.0), 0.0, "{:?}", scale);
+            assert!((scale.to_position(1.0) - 1.0).abs() < 1e-6, "{:?}", scale);
+            let positions: Vec<f32> = samples().map(|scalar| scale.to_position(scalar)).collect();
+            assert!(positions.is_sorted(), "{:?}", scale);
+        }
+    }
+
+    #[test]
+    fn decibel_positions_are_even_steps_in_decibels() {
+        let scale = VolumeScale::Decibels(60.0);
+        // Halfway is 30dB down, a quarter of the way is 45dB down
+        assert!((scale.to_scalar(0.5) - 10f32.powf(-1.5)).abs() < 1e-6);
+        assert!((scale.to_scalar(0.25) - 10f32.powf(-2.25)).abs() < 1e-6);
+        // So every 0.1 step is 6dB, about double or half the scalar
+        assert!((scale.step(0.25, 0.1) / 0.25 - 10f32.powf(0.3)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn perceptual_steps_are_finer_near_the_bottom() {
+        for scale in [VolumeScale::Cubic, VolumeScale::Decibels(60.0)] {
+            let low = scale.step(0.05, 0.05) - 0.05;
+            let high = scale.step(0.8, 0.05) - 0.8;
+            assert!(low < 0.05 && high > 0.05, "{:?}: {} near the bottom, {} near the top", scale, low, high);
+        }
+        assert!((VolumeScale::Linear.step(0.8, 0.05) - 0.85).abs() < 1e-6);
+        assert_eq!(VolumeScale::Cubic.step(0.9, 0.5), 1.0);
+        assert_eq!(VolumeScale::Cubic.step(0.1, -0.5), 0.0);
+    }
+}
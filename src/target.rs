@@ -0,0 +1,840 @@
+use std::fmt;
+use std::time::Duration;
+use serde::Deserialize;
+use crate::audio::AudioBackend;
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::focus::{FocusProvider, FocusedWindow};
+use crate::history::FocusHistory;
+use crate::keyboard::{Modifier, Modifiers};
+use crate::matcher::alias::{self, Pattern};
+use crate::matcher::{SessionMatch, SessionMatcher};
+
+/// What to do with a volume key when the focused application has no audio session
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fallback {
+    /// Let the key through, so the system changes its volume as usual
+    #[default]
+    PassThrough,
+    /// Adjust the default output device
+    Master,
+    /// Adjust the application that was adjusted last
+    LastAdjusted,
+    /// Adjust the application that is making sound right now
+    Playing,
+    /// Adjust the most recently focused application that had audio, until it
+    /// has been out of focus for `recent_timeout_secs`
+    Recent,
+}
+
+/// Settings read from the `[fallback]` table of the config file
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FallbackConfig {
+    /// Used when no rule in `apps` applies, or no window is focused
+    pub default: Fallback,
+    /// Per-application fallbacks, of which the first that applies wins
+    pub apps: Vec<AppFallback>,
+    /// How long an application that lost focus stays a `Recent` target
+    pub recent_timeout_secs: u64,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            default: Fallback::PassThrough,
+            apps: Vec::new(),
+            recent_timeout_secs: 300,
+        }
+    }
+}
+
+impl FallbackConfig {
+    /// Whether any application falls back to `fallback`
+    pub fn uses(&self, fallback: Fallback) -> bool {
+        self.default == fallback || self.apps.iter().any(|app| app.fallback == fallback)
+    }
+
+    /// Picks the fallback for the focused window, if there is one
+    pub fn for_window(&self, window: Option<&FocusedWindow>) -> Fallback {
+        window
+            .and_then(|window| self.apps.iter().find(|app| app.applies_to(window)))
+            .map_or(self.default, |app| app.fallback)
+    }
+}
+
+/// A fallback for the windows matching some patterns, like the `when_*` half of an alias rule
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppFallback {
+    /// Matched against the focused window's executable path
+    #[serde(default)]
+    pub when_executable: Option<Pattern>,
+    /// Matched against the focused window's class
+    #[serde(default)]
+    pub when_class: Option<Pattern>,
+    pub fallback: Fallback,
+}
+
+impl AppFallback {
+    pub fn validate(&self) -> Result<()> {
+        if self.when_executable.is_none() && self.when_class.is_none() {
+            return Err(Error::InvalidConfig("Fallback rule needs when_executable or when_class".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn applies_to(&self, window: &FocusedWindow) -> bool {
+        alias::window_matches(&self.when_executable, &self.when_class, window)
+    }
+}
+
+/// Which window the volume keys follow
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WindowSource {
+    /// The window with keyboard focus
+    #[default]
+    Focus,
+    /// The top-level window under the mouse cursor, e.g. a video on another monitor
+    Cursor,
+}
+
+impl fmt::Display for WindowSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowSource::Focus => write!(f, "focused window"),
+            WindowSource::Cursor => write!(f, "window under the cursor"),
+        }
+    }
+}
+
+/// What a volume key press adjusts, depending on the modifiers held with it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyScope {
+    /// The application in the window the keys follow, or its fallback
+    Focused,
+    /// The default output device
+    Master,
+    /// Every session except the focused application's, to turn background noise down
+    Others,
+}
+
+impl fmt::Display for KeyScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyScope::Focused => write!(f, "the focused application"),
+            KeyScope::Master => write!(f, "the master volume"),
+            KeyScope::Others => write!(f, "every other application"),
+        }
+    }
+}
+
+/// Settings read from the `[targeting.chords]` table: the scope of a volume
+/// key pressed on its own and with each modifier
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChordConfig {
+    pub plain: KeyScope,
+    pub shift: KeyScope,
+    pub ctrl: KeyScope,
+    pub alt: KeyScope,
+}
+
+impl Default for ChordConfig {
+    fn default() -> Self {
+        Self {
+            plain: KeyScope::Focused,
+            shift: KeyScope::Master,
+            ctrl: KeyScope::Others,
+            alt: KeyScope::Focused,
+        }
+    }
+}
+
+impl ChordConfig {
+    /// The scope of a press with `modifiers` held. With several held, Ctrl
+    /// counts before Shift and Shift before Alt.
+    pub fn scope(&self, modifiers: Modifiers) -> KeyScope {
+        if modifiers.ctrl {
+            self.ctrl
+        } else if modifiers.shift {
+            self.shift
+        } else if modifiers.alt {
+            self.alt
+        } else {
+            self.plain
+        }
+    }
+
+    /// The scope of a press with only `modifier` held
+    pub fn for_modifier(&self, modifier: Modifier) -> KeyScope {
+        match modifier {
+            Modifier::Shift => self.shift,
+            Modifier::Ctrl => self.ctrl,
+            Modifier::Alt => self.alt,
+        }
+    }
+}
+
+/// Settings read from the `[targeting]` table of the config file
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TargetingConfig {
+    /// The window the volume keys follow
+    pub window: WindowSource,
+    /// Holding this modifier makes a press follow the other window instead
+    pub switch_modifier: Option<Modifier>,
+    pub chords: ChordConfig,
+}
+
+impl TargetingConfig {
+    pub fn validate(&self) -> Result<()> {
+        // A modifier can't both switch windows and pick a scope that has no window to switch
+        if let Some(modifier) = self.switch_modifier {
+            let scope = self.chords.for_modifier(modifier);
+            if scope != KeyScope::Focused {
+                return Err(Error::InvalidConfig(format!("switch_modifier {:?} is already the chord for {}", modifier, scope)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The window a press with `modifiers` held should follow
+    pub fn source(&self, modifiers: Modifiers) -> WindowSource {
+        let switched = self.switch_modifier.is_some_and(|modifier| modifiers.holds(modifier));
+        match (self.window, switched) {
+            (WindowSource::Focus, true) => WindowSource::Cursor,
+            (WindowSource::Cursor, true) => WindowSource::Focus,
+            (window, false) => window,
+        }
+    }
+}
+
+/// How often input loops call `TargetSelector::observe_focus` while no keys are pressed
+pub const FOCUS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What a volume key press should adjust
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target<S> {
+    Sessions(Vec<S>),
+    Master,
+    /// Nothing; the key should do whatever it does without this program
+    PassThrough,
+}
+
+/// Decides what each volume key press adjusts: the pinned application's
+/// sessions, the focused (or hovered) application's, or failing that its
+/// configured fallback.
+pub struct TargetSelector<S> {
+    matcher: SessionMatcher<S>,
+    fallback: FallbackConfig,
+    targeting: TargetingConfig,
+    // Executable path of the application the keys are pinned to
+    pinned: Option<String>,
+    // The window of the last application that was adjusted directly
+    last_adjusted: Option<FocusedWindow>,
+    history: FocusHistory,
+    // The process and window the history last heard about
+    observed: Option<(u32, u64)>,
+}
+
+impl<S: Clone + PartialEq> TargetSelector<S> {
+    /// Creates a selector that matches sessions against the processes running on this machine
+    pub fn new(config: &Config) -> Self {
+        let mut selector = Self::with_matcher(SessionMatcher::new(config.matching.clone()), config.fallback.clone());
+        selector.set_targeting(config.targeting.clone());
+        selector
+    }
+
+    pub fn with_matcher(matcher: SessionMatcher<S>, fallback: FallbackConfig) -> Self {
+        Self::with_clock(matcher, fallback, SystemClock)
+    }
+
+    /// Creates a selector whose focus history is timed by `clock`
+    pub fn with_clock(matcher: SessionMatcher<S>, fallback: FallbackConfig, clock: impl Clock + 'static) -> Self {
+        let timeout = Duration::from_secs(fallback.recent_timeout_secs);
+        Self {
+            matcher,
+            fallback,
+            targeting: TargetingConfig::default(),
+            pinned: None,
+            last_adjusted: None,
+            history: FocusHistory::new(clock, timeout),
+            observed: None,
+        }
+    }
+
+    /// Chooses which window the volume keys follow
+    pub fn set_targeting(&mut self, targeting: TargetingConfig) {
+        self.targeting = targeting;
+    }
+
+    /// Whether `observe_focus` should be called between key presses, which
+    /// is only needed to keep the focus history for `Fallback::Recent`
+    pub fn wants_focus_updates(&self) -> bool {
+        self.fallback.uses(Fallback::Recent)
+    }
+
+    /// Notes a change of focus in the focus history.
+    ///
+    /// Only new windows are looked up, so an application that starts playing
+    /// while it has focus is noticed on the next key press instead.
+    pub fn observe_focus<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, provider: &F, backend: &B) {
+        let window = match provider.focused_window() {
+            Ok(window) => Some(window),
+            Err(Error::NoFocusedWindow) => None,
+            Err(e) => {
+                println!("Error getting focused window: {}", e);
+                return;
+            }
+        };
+        if window.as_ref().map(|window| (window.pid, window.window_id)) == self.observed {
+            return;
+        }
+
+        let has_audio = match &window {
+            Some(window) => match self.matcher.find_sessions(backend, window) {
+                Ok(_) => true,
+                Err(Error::SessionNotFound { .. }) => false,
+                Err(e) => {
+                    println!("Error getting focused window session: {}", e);
+                    return;
+                }
+            },
+            None => false,
+        };
+        self.record_focus(window.as_ref(), has_audio);
+    }
+
+    fn record_focus(&mut self, window: Option<&FocusedWindow>, has_audio: bool) {
+        self.observed = window.map(|window| (window.pid, window.window_id));
+        self.history.observe(window, has_audio);
+    }
+
+    /// Rescans the sessions on the next press, after one of them turned out to be gone
+    pub fn mark_stale(&mut self) {
+        self.matcher.mark_stale();
+    }
+
+    /// Every session there is, whatever has focus
+    pub fn all_sessions<B: AudioBackend<Session = S>>(&mut self, backend: &B) -> Result<Vec<S>> {
+        self.matcher.all_sessions(backend)
+    }
+
+    /// Executable path of the application the volume keys are pinned to, if any
+    pub fn pinned(&self) -> Option<&str> {
+        self.pinned.as_deref()
+    }
+
+    /// Sends the volume keys to the application running `path`, whichever window has focus
+    pub fn pin(&mut self, path: impl Into<String>) {
+        self.pinned = Some(path.into());
+    }
+
+    pub fn unpin(&mut self) {
+        self.pinned = None;
+    }
+
+    /// Unpins the volume keys, or pins them to the focused application.
+    ///
+    /// When the focused window has no audio, which is the case while the tray
+    /// menu has focus, the last adjusted application is pinned instead.
+    pub fn toggle_pin<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, provider: &F, backend: &B) {
+        if let Some(path) = self.pinned.take() {
+            println!("Unpinned volume keys from {}", path);
+            return;
+        }
+
+        match self.pin_candidate(provider, backend) {
+            Some(path) => {
+                println!("Pinned volume keys to {}", path);
+                self.pinned = Some(path);
+            }
+            None => println!("Nothing to pin, no application with audio has been focused"),
+        }
+    }
+
+    // The executable playing the focused or last adjusted application's audio, which for
+    // launchers and terminals is not the window's own
+    fn pin_candidate<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, provider: &F, backend: &B) -> Option<String> {
+        let windows = provider.focused_window().ok().into_iter().chain(self.last_adjusted.clone());
+        for window in windows {
+            match self.matcher.find_sessions(backend, &window) {
+                Ok(found) => return Some(self.matcher.session_path(&found.sessions[0]).unwrap_or(window.path)),
+                Err(Error::SessionNotFound { .. }) => continue,
+                Err(e) => {
+                    println!("Error getting session to pin: {}", e);
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    /// Picks what a press with `modifiers` held adjusts
+    pub fn select<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, provider: &F, backend: &B, modifiers: Modifiers) -> Target<S> {
+        match self.targeting.chords.scope(modifiers) {
+            KeyScope::Focused => self.select_focused(provider, backend, modifiers),
+            KeyScope::Master => Target::Master,
+            KeyScope::Others => self.select_others(provider, backend, modifiers),
+        }
+    }
+
+    fn select_focused<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, provider: &F, backend: &B, modifiers: Modifiers) -> Target<S> {
+        // The pin stays while its application isn't running, so the keys go back to it when it restarts
+        if let Some(path) = &self.pinned {
+            match self.matcher.find_sessions_for_path(backend, path) {
+                Ok(found) => return Target::Sessions(found.sessions),
+                Err(Error::SessionNotFound { .. }) => println!("Pinned application {} has no audio, using the focused window", path),
+                Err(e) => println!("Error getting pinned application session: {}", e),
+            }
+        }
+
+        // Failures other than there being nothing to adjust let the key through, so it never just stops working
+        let source = self.targeting.source(modifiers);
+        let Some(window) = target_window(provider, source) else {
+            return Target::PassThrough;
+        };
+
+        // Only focus changes belong in the focus history
+        let focused = source == WindowSource::Focus;
+        if let Some(window) = &window {
+            match self.matcher.find_sessions(backend, window) {
+                Ok(SessionMatch { sessions, .. }) => {
+                    if focused {
+                        self.record_focus(Some(window), true);
+                    }
+                    self.last_adjusted = Some(window.clone());
+                    return Target::Sessions(sessions);
+                }
+                Err(Error::SessionNotFound { .. }) => {}
+                Err(e) => {
+                    println!("Error getting {} session: {}", source, e);
+                    return Target::PassThrough;
+                }
+            }
+        }
+        if focused {
+            self.record_focus(window.as_ref(), false);
+        }
+
+        let fallback = self.fallback.for_window(window.as_ref());
+        self.fallback_target(fallback, backend)
+    }
+
+    // Every session but those of the application in the window the keys follow
+    fn select_others<F: FocusProvider, B: AudioBackend<Session = S>>(&mut self, provider: &F, backend: &B, modifiers: Modifiers) -> Target<S> {
+        let source = self.targeting.source(modifiers);
+        let Some(window) = target_window(provider, source) else {
+            return Target::PassThrough;
+        };
+
+        let excluded = match &window {
+            Some(window) => match self.matcher.find_sessions(backend, window) {
+                Ok(found) => found.sessions,
+                Err(Error::SessionNotFound { .. }) => Vec::new(),
+                Err(e) => {
+                    println!("Error getting {} session: {}", source, e);
+                    return Target::PassThrough;
+                }
+            },
+            None => Vec::new(),
+        };
+
+        // With only the application itself playing, the key does what it would have done anyway
+        match self.matcher.all_sessions(backend) {
+            Ok(sessions) => {
+                let others: Vec<S> = sessions.into_iter().filter(|session| !excluded.contains(session)).collect();
+                if others.is_empty() {
+                    return Target::PassThrough;
+                }
+                Target::Sessions(others)
+            }
+            Err(e) => {
+                println!("Error getting sessions: {}", e);
+                Target::PassThrough
+            }
+        }
+    }
+
+    fn fallback_target<B: AudioBackend<Session = S>>(&mut self, fallback: Fallback, backend: &B) -> Target<S> {
+        let sessions = match fallback {
+            Fallback::PassThrough => return Target::PassThrough,
+            Fallback::Master => return Target::Master,
+            Fallback::LastAdjusted => match self.last_adjusted.clone() {
+                // Matched again, since its sessions may have come and gone in the meantime
+                Some(window) => self.matcher.find_sessions(backend, &window).map(|found| found.sessions),
+                None => Ok(Vec::new()),
+            },
+            Fallback::Playing => self.matcher.playing_sessions(backend),
+            Fallback::Recent => self.recent_sessions(backend),
+        };
+
+        // With nothing to fall back to either, the key does what it would have done anyway
+        match sessions {
+            Ok(sessions) if !sessions.is_empty() => Target::Sessions(sessions),
+            Ok(_) | Err(Error::SessionNotFound { .. }) => Target::PassThrough,
+            Err(e) => {
+                println!("Error finding fallback sessions: {}", e);
+                Target::PassThrough
+            }
+        }
+    }
+
+    // The newest application in the history that still has sessions
+    fn recent_sessions<B: AudioBackend<Session = S>>(&mut self, backend: &B) -> Result<Vec<S>> {
+        let recent: Vec<FocusedWindow> = self.history.recent().into_iter().cloned().collect();
+        for window in recent {
+            match self.matcher.find_sessions(backend, &window) {
+                Ok(found) => return Ok(found.sessions),
+                Err(Error::SessionNotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Vec::new())
+    }
+}
+
+// The window the keys follow, which is Some(None) when there is no such window
+// and None when it couldn't be told
+fn target_window<F: FocusProvider>(provider: &F, source: WindowSource) -> Option<Option<FocusedWindow>> {
+    let window = match source {
+        WindowSource::Focus => provider.focused_window(),
+        WindowSource::Cursor => provider.window_under_cursor(),
+    };
+    match window {
+        Ok(window) => Some(Some(window)),
+        Err(Error::NoFocusedWindow | Error::NoWindowUnderCursor) => Some(None),
+        Err(e) => {
+            println!("Error getting {}: {}", source, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{MockAudioBackend, MockSession};
+    use crate::clock::FakeClock;
+    use crate::focus::FakeFocusProvider;
+    use crate::matcher::MatcherConfig;
+    use crate::process::ProcessTable;
+
+    fn selector(fallback: FallbackConfig) -> TargetSelector<MockSession> {
+        let matcher = SessionMatcher::with_processes(MatcherConfig::default(), ProcessTable::new());
+        TargetSelector::with_matcher(matcher, fallback)
+    }
+
+    fn fallback(default: Fallback) -> FallbackConfig {
+        FallbackConfig { default, ..Default::default() }
+    }
+
+    #[test]
+    fn focused_sessions_come_before_any_fallback() {
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+
+        let mut targets = selector(fallback(Fallback::Master));
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![mpv]));
+    }
+
+    #[test]
+    fn applications_without_sessions_use_the_fallback() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(30, "/usr/bin/gedit");
+
+        assert_eq!(selector(fallback(Fallback::PassThrough)).select(&provider, &backend, Modifiers::NONE), Target::PassThrough);
+        assert_eq!(selector(fallback(Fallback::Master)).select(&provider, &backend, Modifiers::NONE), Target::Master);
+
+        provider.clear();
+        assert_eq!(selector(fallback(Fallback::Master)).select(&provider, &backend, Modifiers::NONE), Target::Master);
+    }
+
+    #[test]
+    fn last_adjusted_application_is_remembered() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let provider = FakeFocusProvider::new();
+        let mut targets = selector(fallback(Fallback::LastAdjusted));
+
+        // Nothing has been adjusted yet
+        provider.focus_process(30, "/usr/bin/gedit");
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::PassThrough);
+
+        provider.focus_process(10, "/usr/bin/spotify");
+        targets.select(&provider, &backend, Modifiers::NONE);
+        provider.focus_process(30, "/usr/bin/gedit");
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![spotify]));
+
+        // Once it is gone there is nothing to fall back to
+        backend.remove_session(spotify);
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::PassThrough);
+    }
+
+    #[test]
+    fn playing_application_is_targeted_as_a_whole() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(10, "/usr/bin/spotify");
+        let tab = backend.add_session(20, "/usr/lib/firefox/firefox");
+        let other_tab = backend.add_session(21, "/usr/lib/firefox/firefox");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(30, "/usr/bin/gedit");
+        let mut targets = selector(fallback(Fallback::Playing));
+
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::PassThrough, "nothing is playing");
+
+        backend.set_playing_of(other_tab, true);
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![tab, other_tab]));
+    }
+
+    #[test]
+    fn recently_focused_application_is_sticky_until_it_times_out() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let provider = FakeFocusProvider::new();
+        let clock = FakeClock::new();
+        let matcher = SessionMatcher::with_processes(MatcherConfig::default(), ProcessTable::new());
+        let config = FallbackConfig { default: Fallback::Recent, recent_timeout_secs: 60, ..Default::default() };
+        let mut targets = TargetSelector::with_clock(matcher, config, clock.clone());
+        assert!(targets.wants_focus_updates());
+
+        // Spotify only had focus, its volume was never touched
+        provider.focus_process(10, "/usr/bin/spotify");
+        targets.observe_focus(&provider, &backend);
+        clock.advance(Duration::from_secs(600));
+        provider.focus_process(30, "/usr/lib/firefox/firefox");
+        targets.observe_focus(&provider, &backend);
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![spotify]));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::PassThrough);
+    }
+
+    #[test]
+    fn recent_applications_without_sessions_are_skipped() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        let mut targets = selector(FallbackConfig { default: Fallback::Recent, ..Default::default() });
+
+        for (pid, path) in [(10, "/usr/bin/spotify"), (20, "/usr/bin/mpv"), (30, "/usr/bin/gedit")] {
+            provider.focus_process(pid, path);
+            targets.observe_focus(&provider, &backend);
+        }
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![mpv]));
+
+        backend.remove_session(mpv);
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![spotify]));
+    }
+
+    #[test]
+    fn fallbacks_can_be_set_per_application() {
+        let config = FallbackConfig {
+            apps: vec![
+                AppFallback { when_executable: Some(Pattern::new("*/code").unwrap()), when_class: None, fallback: Fallback::Master },
+                AppFallback { when_executable: None, when_class: Some(Pattern::new("re:^steam_app_").unwrap()), fallback: Fallback::Playing },
+            ],
+            ..Default::default()
+        };
+
+        let window = |path: &str, class: &str| FocusedWindow { path: path.to_string(), class: class.to_string(), ..Default::default() };
+        let cases = [
+            (Some(window("/usr/share/code/code", "Code")), Fallback::Master),
+            (Some(window("/usr/bin/proton", "steam_app_570")), Fallback::Playing),
+            (Some(window("/usr/bin/gedit", "Gedit")), Fallback::PassThrough),
+            (None, Fallback::PassThrough),
+        ];
+
+        for (window, expected) in cases {
+            assert_eq!(config.for_window(window.as_ref()), expected, "{:?}", window);
+        }
+    }
+
+    #[test]
+    fn pinned_application_is_adjusted_whatever_has_focus() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        let mut targets = selector(fallback(Fallback::PassThrough));
+
+        provider.focus_process(10, "/usr/bin/spotify");
+        targets.toggle_pin(&provider, &backend);
+        assert_eq!(targets.pinned(), Some("/usr/bin/spotify"));
+
+        provider.focus_process(20, "/usr/bin/mpv");
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![spotify]));
+        provider.focus_process(30, "/usr/bin/gedit");
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![spotify]));
+
+        targets.toggle_pin(&provider, &backend);
+        assert_eq!(targets.pinned(), None);
+        provider.focus_process(20, "/usr/bin/mpv");
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![mpv]));
+    }
+
+    #[test]
+    fn pinning_without_audio_in_focus_takes_the_last_adjusted_application() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(10, "/usr/bin/spotify");
+        let provider = FakeFocusProvider::new();
+        let mut targets = selector(fallback(Fallback::PassThrough));
+
+        // Nothing to pin yet
+        provider.focus_process(1, "/usr/bin/focused-window-volume");
+        targets.toggle_pin(&provider, &backend);
+        assert_eq!(targets.pinned(), None);
+
+        provider.focus_process(10, "/usr/bin/spotify");
+        targets.select(&provider, &backend, Modifiers::NONE);
+        provider.focus_process(1, "/usr/bin/focused-window-volume");
+        targets.toggle_pin(&provider, &backend);
+        assert_eq!(targets.pinned(), Some("/usr/bin/spotify"));
+    }
+
+    #[test]
+    fn pinning_a_terminal_pins_the_player_inside_it() {
+        // terminal(100) -> shell(101) -> mpv(102, playing)
+        let backend = MockAudioBackend::new();
+        backend.add_session(102, "/usr/bin/mpv");
+        let processes = ProcessTable::from_parents([(100, 1), (101, 100), (102, 101)]);
+        let matcher = SessionMatcher::with_processes(MatcherConfig::default(), processes);
+        let mut targets = TargetSelector::with_matcher(matcher, fallback(Fallback::PassThrough));
+        let provider = FakeFocusProvider::new();
+
+        provider.focus_process(100, "/usr/bin/alacritty");
+        targets.toggle_pin(&provider, &backend);
+        assert_eq!(targets.pinned(), Some("/usr/bin/mpv"));
+    }
+
+    #[test]
+    fn pin_survives_the_application_restarting() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+        let mut targets = selector(fallback(Fallback::PassThrough));
+        targets.pin("/usr/bin/spotify");
+
+        // While it is closed the keys go to the focused window as usual
+        backend.remove_session(spotify);
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![mpv]));
+
+        let restarted = backend.add_session(11, "/usr/bin/spotify");
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![restarted]));
+    }
+
+    #[test]
+    fn window_under_the_cursor_can_be_targeted_instead() {
+        let backend = MockAudioBackend::new();
+        let browser = backend.add_session(10, "/usr/lib/firefox/firefox");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(10, "/usr/lib/firefox/firefox");
+        provider.hover_process(20, "/usr/bin/mpv");
+        let alt = Modifiers { alt: true, ..Modifiers::NONE };
+
+        let mut targets = selector(fallback(Fallback::PassThrough));
+        targets.set_targeting(TargetingConfig { window: WindowSource::Cursor, ..Default::default() });
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![mpv]));
+        assert_eq!(targets.select(&provider, &backend, alt), Target::Sessions(vec![mpv]), "no modifier switches");
+
+        // Over the desktop there is nothing to adjust
+        provider.clear_hover();
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::PassThrough);
+
+        provider.hover_process(20, "/usr/bin/mpv");
+        targets.set_targeting(TargetingConfig { switch_modifier: Some(Modifier::Alt), ..Default::default() });
+        assert_eq!(targets.select(&provider, &backend, Modifiers::NONE), Target::Sessions(vec![browser]));
+        assert_eq!(targets.select(&provider, &backend, alt), Target::Sessions(vec![mpv]));
+    }
+
+    #[test]
+    fn switch_modifier_swaps_the_window_source() {
+        let shift = Modifiers { shift: true, ..Modifiers::NONE };
+        let ctrl = Modifiers { ctrl: true, ..Modifiers::NONE };
+        let cases = [
+            (WindowSource::Focus, Modifiers::NONE, WindowSource::Focus),
+            (WindowSource::Focus, shift, WindowSource::Cursor),
+            (WindowSource::Focus, ctrl, WindowSource::Focus),
+            (WindowSource::Cursor, Modifiers::NONE, WindowSource::Cursor),
+            (WindowSource::Cursor, shift, WindowSource::Focus),
+        ];
+
+        for (window, modifiers, expected) in cases {
+            let targeting = TargetingConfig { window, switch_modifier: Some(Modifier::Shift), ..Default::default() };
+            assert_eq!(targeting.source(modifiers), expected, "{:?} with {:?}", window, modifiers);
+        }
+    }
+
+    #[test]
+    fn chords_pick_the_scope_by_modifier() {
+        let held = |shift, ctrl, alt| Modifiers { shift, ctrl, alt };
+        let defaults = ChordConfig::default();
+        let swapped = ChordConfig { shift: KeyScope::Others, ctrl: KeyScope::Master, alt: KeyScope::Master, ..Default::default() };
+        let cases = [
+            (&defaults, held(false, false, false), KeyScope::Focused),
+            (&defaults, held(true, false, false), KeyScope::Master),
+            (&defaults, held(false, true, false), KeyScope::Others),
+            (&defaults, held(false, false, true), KeyScope::Focused),
+            (&defaults, held(true, true, true), KeyScope::Others),
+            (&defaults, held(true, false, true), KeyScope::Master),
+            (&swapped, held(true, false, false), KeyScope::Others),
+            (&swapped, held(false, false, true), KeyScope::Master),
+        ];
+
+        for (chords, modifiers, expected) in cases {
+            assert_eq!(chords.scope(modifiers), expected, "{:?} with {:?}", chords, modifiers);
+        }
+    }
+
+    #[test]
+    fn chords_target_the_master_or_everything_but_the_focused_application() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let tab = backend.add_session(20, "/usr/lib/firefox/firefox");
+        let other_tab = backend.add_session(21, "/usr/lib/firefox/firefox");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(19, "/usr/lib/firefox/firefox");
+        let mut targets = selector(fallback(Fallback::PassThrough));
+
+        let shift = Modifiers { shift: true, ..Modifiers::NONE };
+        let ctrl = Modifiers { ctrl: true, ..Modifiers::NONE };
+        assert_eq!(targets.select(&provider, &backend, shift), Target::Master);
+        assert_eq!(targets.select(&provider, &backend, ctrl), Target::Sessions(vec![spotify]));
+
+        // Without a focused application everything is in the background
+        provider.focus_process(30, "/usr/bin/gedit");
+        assert_eq!(targets.select(&provider, &backend, ctrl), Target::Sessions(vec![spotify, tab, other_tab]));
+    }
+
+    #[test]
+    fn others_chord_passes_through_when_nothing_else_has_sessions() {
+        let backend = MockAudioBackend::new();
+        let provider = FakeFocusProvider::new();
+        let mut targets = selector(fallback(Fallback::Master));
+        let ctrl = Modifiers { ctrl: true, ..Modifiers::NONE };
+
+        assert_eq!(targets.select(&provider, &backend, ctrl), Target::PassThrough);
+
+        // Only the focused application is playing
+        backend.add_session(10, "/usr/bin/spotify");
+        provider.focus_process(10, "/usr/bin/spotify");
+        assert_eq!(targets.select(&provider, &backend, ctrl), Target::PassThrough);
+
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        assert_eq!(targets.select(&provider, &backend, ctrl), Target::Sessions(vec![mpv]));
+    }
+}
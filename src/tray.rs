@@ -6,24 +6,37 @@ use std::ptr::null;
 use windows::core::*;
 use windows::Win32::Foundation::*;
 use windows::Win32::UI::WindowsAndMessaging::*;
+use windows::Win32::UI::Input::KeyboardAndMouse::{RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT};
 use windows::Win32::UI::Shell::*;
 use windows::Win32::Graphics::Gdi::*;
 use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use crate::keyboard::{Hotkey, HotkeyConfig};
+use crate::worker::CommandSender;
 
 const WM_APP_NOTIFY: u32 = WM_APP + 1;
 const IDM_EXIT: u32 = 1001;
+const IDM_TOGGLE_PIN: u32 = 1002;
+
+// IDs of the global hotkeys registered on the tray window
+const HOTKEY_TOGGLE_PIN: i32 = 1;
+const HOTKEY_TOGGLE_SOLO: i32 = 2;
 
 pub enum TrayEvent {
     Quit,
+    TogglePin,
+    ToggleSolo,
 }
 
 pub struct Tray {
     hwnd: HWND,
     rx: mpsc::Receiver<TrayEvent>,
+    commands: CommandSender,
 }
 
 impl Tray {
-    pub fn new(app_name: &str) -> std::result::Result<Self, Box<dyn std::error::Error>> {
+    /// Creates the tray icon. Its menu and the configured hotkeys send pin
+    /// and solo toggles to the worker behind `commands`.
+    pub fn new(app_name: &str, commands: CommandSender, hotkeys: &HotkeyConfig) -> std::result::Result<Self, Box<dyn std::error::Error>> {
         let (tx, rx) = mpsc::channel();
         
         // Clone tx and create a boxed copy to be stored in the window
@@ -103,12 +116,22 @@ impl Tray {
                 return Err(Error::from_win32().into());
             }
             
+            // Another program may own a hotkey already, which still leaves the menu
+            for (id, hotkey) in [(HOTKEY_TOGGLE_PIN, hotkeys.pin), (HOTKEY_TOGGLE_SOLO, hotkeys.solo)] {
+                if let Some(hotkey) = hotkey
+                    && let Err(e) = RegisterHotKey(hwnd, id, hotkey_modifiers(&hotkey), hotkey.key as u32)
+                {
+                    println!("Error registering hotkey {}: {}", hotkey, e);
+                }
+            }
+            
             hwnd
         };
         
         Ok(Self {
             hwnd,
             rx,
+            commands,
         })
     }
     
@@ -134,6 +157,16 @@ impl Tray {
                         println!("Quitting application...");
                         break;
                     },
+                    Ok(TrayEvent::TogglePin) => {
+                        if !self.commands.toggle_pin() {
+                            println!("Error toggling pin: the worker has stopped");
+                        }
+                    },
+                    Ok(TrayEvent::ToggleSolo) => {
+                        if !self.commands.toggle_solo() {
+                            println!("Error toggling solo: the worker has stopped");
+                        }
+                    },
                     Err(mpsc::TryRecvError::Empty) => {
                         // No message available, sleep a bit to avoid busy waiting
                         std::thread::sleep(std::time::Duration::from_millis(10));
@@ -194,6 +227,8 @@ impl Drop for Tray {
             };
             
             let _ = Shell_NotifyIconW(NIM_DELETE, &nid);
+            let _ = UnregisterHotKey(self.hwnd, HOTKEY_TOGGLE_PIN);
+            let _ = UnregisterHotKey(self.hwnd, HOTKEY_TOGGLE_SOLO);
             DestroyWindow(self.hwnd);
         }
     }
@@ -219,6 +254,7 @@ extern "system" fn window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPA
                     WM_CONTEXTMENU | WM_RBUTTONUP => {
                         // Show context menu
                         let hmenu = CreatePopupMenu().unwrap();
+                        AppendMenuW(hmenu, MENU_ITEM_FLAGS(0), IDM_TOGGLE_PIN as usize, w!("Pin / unpin volume keys")).unwrap();
                         AppendMenuW(hmenu, MENU_ITEM_FLAGS(0), IDM_EXIT as usize, w!("Quit")).unwrap();
                         
                         // Get cursor position
@@ -256,9 +292,21 @@ extern "system" fn window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPA
                         }
                         LRESULT(0)
                     },
+                    IDM_TOGGLE_PIN => {
+                        send_event(tx_ptr, TrayEvent::TogglePin);
+                        LRESULT(0)
+                    },
                     _ => DefWindowProcW(hwnd, msg, wparam, lparam),
                 }
             },
+            WM_HOTKEY => {
+                match wparam.0 as i32 {
+                    HOTKEY_TOGGLE_PIN => send_event(tx_ptr, TrayEvent::TogglePin),
+                    HOTKEY_TOGGLE_SOLO => send_event(tx_ptr, TrayEvent::ToggleSolo),
+                    _ => {}
+                }
+                LRESULT(0)
+            },
             WM_DESTROY => {
                 PostQuitMessage(0);
                 LRESULT(0)
@@ -268,6 +316,31 @@ extern "system" fn window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPA
     }
 }
 
+// Autorepeat is left out so holding a hotkey only toggles once
+fn hotkey_modifiers(hotkey: &Hotkey) -> HOT_KEY_MODIFIERS {
+    let mut modifiers = MOD_NOREPEAT;
+    if hotkey.modifiers.shift {
+        modifiers |= MOD_SHIFT;
+    }
+    if hotkey.modifiers.ctrl {
+        modifiers |= MOD_CONTROL;
+    }
+    if hotkey.modifiers.alt {
+        modifiers |= MOD_ALT;
+    }
+    modifiers
+}
+
+// Sends an event to the tray's run loop, if the window has its channel yet
+unsafe fn send_event(tx_ptr: *const mpsc::Sender<TrayEvent>, event: TrayEvent) {
+    if !tx_ptr.is_null() {
+        let tx = unsafe { &*tx_ptr };
+        if let Err(e) = tx.send(event) {
+            eprintln!("Failed to send tray event: {}", e);
+        }
+    }
+}
+
 // Helper function to extract the low-order word from a value
 #[inline]
 fn LOWORD(value: u32) -> u32 {
@@ -0,0 +1,453 @@
+use std::collections::VecDeque;
+use std::time::{Instant, Duration};
+use crate::acceleration::{AccelerationConfig, AccelerationCurve, Reversal};
+use crate::audio::{self, AudioBackend};
+use crate::clock::{Clock, SystemClock};
+use crate::error::{Error, Result};
+use crate::focus::FocusProvider;
+use crate::keyboard::Modifiers;
+use crate::scale::VolumeScale;
+use crate::target::{Target, TargetSelector};
+
+// The first press after a fresh start counts as coming this long after the last
+const FIRST_PRESS_MS: u64 = 1000;
+
+/// Which way a volume press moves the volume
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// Works out how far each volume key press moves the volume, from how
+/// quickly the presses are coming in the same direction
+pub struct VolumeKeyState {
+    clock: Box<dyn Clock>,
+    last_pressed: Option<(Instant, Direction)>,
+    base_increment: f32,
+    scale: VolumeScale,
+    // The latest time deltas (in milliseconds), oldest first
+    time_deltas: VecDeque<u64>,
+    max_history_size: usize,
+    curve: Box<dyn AccelerationCurve>,
+    on_reversal: Reversal,
+    idle_reset: Duration,
+}
+
+impl VolumeKeyState {
+    pub fn new(config: &AccelerationConfig) -> Self {
+        Self::with_clock(config, SystemClock)
+    }
+
+    /// Creates the state with presses timed by `clock`
+    pub fn with_clock(config: &AccelerationConfig, clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+            last_pressed: None,
+            base_increment: config.base_increment,
+            scale: config.scale,
+            time_deltas: VecDeque::with_capacity(config.history),
+            max_history_size: config.history.max(1),
+            curve: config.curve.build(),
+            on_reversal: config.on_reversal,
+            idle_reset: Duration::from_millis(config.idle_reset_ms),
+        }
+    }
+
+    /// The scale the steps are measured on
+    pub fn scale(&self) -> VolumeScale {
+        self.scale
+    }
+
+    /// Records a press made now and gives the volume step it should make
+    pub fn adjustment(&mut self, direction: Direction) -> f32 {
+        let now = self.clock.now();
+        let last_pressed = self.last_pressed.replace((now, direction));
+
+        let elapsed_ms = match last_pressed {
+            Some((last, _)) if now.duration_since(last) >= self.idle_reset => self.start_over(),
+            Some((_, last_direction)) if last_direction != direction && self.on_reversal == Reversal::Reset => self.start_over(),
+            Some((last, last_direction)) => {
+                if last_direction != direction && let Reversal::Damp(damp) = self.on_reversal {
+                    // The presses the other way count as slower than they were
+                    for delta in &mut self.time_deltas {
+                        *delta = (*delta as f32 / damp) as u64;
+                    }
+                }
+                now.duration_since(last).as_millis() as u64
+            }
+            None => FIRST_PRESS_MS,
+        };
+
+        if self.time_deltas.len() == self.max_history_size {
+            self.time_deltas.pop_front();
+        }
+        self.time_deltas.push_back(elapsed_ms);
+
+        let avg_elapsed_ms = self.time_deltas.iter().sum::<u64>() / self.time_deltas.len() as u64;
+        let acceleration_factor = self.curve.factor(Duration::from_millis(avg_elapsed_ms));
+        self.base_increment * acceleration_factor
+    }
+
+    // Forgets the history, giving the interval the press should count as
+    fn start_over(&mut self) -> u64 {
+        self.time_deltas.clear();
+        FIRST_PRESS_MS
+    }
+}
+
+/// A volume key press, independent of the input source that captured it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VolumeKey {
+    Up,
+    Down,
+    Mute,
+}
+
+impl VolumeKey {
+    /// Which way the key moves the volume; mute has no direction
+    pub fn direction(self) -> Option<Direction> {
+        match self {
+            VolumeKey::Up => Some(Direction::Up),
+            VolumeKey::Down => Some(Direction::Down),
+            VolumeKey::Mute => None,
+        }
+    }
+}
+
+impl Direction {
+    pub fn key(self) -> VolumeKey {
+        match self {
+            Direction::Up => VolumeKey::Up,
+            Direction::Down => VolumeKey::Down,
+        }
+    }
+}
+
+/// What became of a volume key press
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyOutcome {
+    /// The key adjusted some volume, or tried to
+    Handled,
+    /// There was nothing to adjust, so the caller should let the system have the key
+    PassThrough,
+}
+
+pub fn handle_volume_key<F: FocusProvider, B: AudioBackend>(
+    key: VolumeKey,
+    modifiers: Modifiers,
+    provider: &F,
+    backend: &B,
+    targets: &mut TargetSelector<B::Session>,
+    acceleration: &mut VolumeKeyState,
+) -> KeyOutcome {
+    let scale = acceleration.scale();
+    adjust(key, modifiers, provider, backend, targets, scale, |direction| acceleration.adjustment(direction))
+}
+
+/// Moves the volume by `amount` for a key that is being held down, leaving
+/// acceleration out of it
+pub fn ramp_volume<F: FocusProvider, B: AudioBackend>(
+    direction: Direction,
+    amount: f32,
+    scale: VolumeScale,
+    modifiers: Modifiers,
+    provider: &F,
+    backend: &B,
+    targets: &mut TargetSelector<B::Session>,
+) -> KeyOutcome {
+    adjust(direction.key(), modifiers, provider, backend, targets, scale, |_| amount)
+}
+
+// Applies the key to whatever it targets, with `step` giving how far up or down to go
+fn adjust<F: FocusProvider, B: AudioBackend>(
+    key: VolumeKey,
+    modifiers: Modifiers,
+    provider: &F,
+    backend: &B,
+    targets: &mut TargetSelector<B::Session>,
+    scale: VolumeScale,
+    step: impl FnOnce(Direction) -> f32,
+) -> KeyOutcome {
+    match targets.select(provider, backend, modifiers) {
+        Target::Sessions(sessions) => {
+            if let Err(e) = adjust_sessions(key, backend, &sessions, scale, step) {
+                report_audio_error(key, e, targets);
+            }
+            KeyOutcome::Handled
+        }
+        Target::Master => match adjust_master(key, backend, scale, step) {
+            Ok(()) => KeyOutcome::Handled,
+            // Most likely a backend without a master volume, which the system can still change
+            Err(e) => {
+                println!("Error adjusting master volume: {}", e);
+                KeyOutcome::PassThrough
+            }
+        },
+        Target::PassThrough => KeyOutcome::PassThrough,
+    }
+}
+
+fn adjust_sessions<B: AudioBackend>(
+    key: VolumeKey,
+    backend: &B,
+    sessions: &[B::Session],
+    scale: VolumeScale,
+    step: impl FnOnce(Direction) -> f32,
+) -> Result<()> {
+    // Up and down move the loudest session by the adjustment and scale the others along with it,
+    // mute mutes every session unless they are all muted already
+    match key {
+        VolumeKey::Up => audio::step_sessions_volume(backend, sessions, scale, step(Direction::Up)).map(drop),
+        VolumeKey::Down => audio::step_sessions_volume(backend, sessions, scale, -step(Direction::Down)).map(drop),
+        VolumeKey::Mute => audio::toggle_sessions_mute(backend, sessions).map(drop),
+    }
+}
+
+fn adjust_master<B: AudioBackend>(key: VolumeKey, backend: &B, scale: VolumeScale, step: impl FnOnce(Direction) -> f32) -> Result<()> {
+    match key {
+        VolumeKey::Up => audio::step_master_volume(backend, scale, step(Direction::Up)).map(drop),
+        VolumeKey::Down => audio::step_master_volume(backend, scale, -step(Direction::Down)).map(drop),
+        VolumeKey::Mute => audio::toggle_master_mute(backend).map(drop),
+    }
+}
+
+// When a session has expired the sessions are rescanned on the next press
+fn report_audio_error<S: Clone + PartialEq>(key: VolumeKey, e: Error, targets: &mut TargetSelector<S>) {
+    if let Error::SessionExpired = e {
+        targets.mark_stale();
+    }
+    let action = match key {
+        VolumeKey::Up | VolumeKey::Down => "adjusting volume",
+        VolumeKey::Mute => "toggling mute",
+    };
+    println!("Error {}: {}", action, e);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceleration::{CurveConfig, Linear, Step, Stepped};
+    use crate::audio::MockAudioBackend;
+    use crate::clock::FakeClock;
+    use crate::focus::FakeFocusProvider;
+    use crate::matcher::{MatcherConfig, SessionMatcher};
+    use crate::process::ProcessTable;
+    use crate::target::{Fallback, FallbackConfig};
+
+    fn acceleration() -> VolumeKeyState {
+        VolumeKeyState::with_clock(&AccelerationConfig::default(), FakeClock::new())
+    }
+
+    fn targets(fallback: Fallback) -> TargetSelector<crate::audio::MockSession> {
+        let matcher = SessionMatcher::with_processes(MatcherConfig::default(), ProcessTable::new());
+        TargetSelector::with_matcher(matcher, FallbackConfig { default: fallback, ..Default::default() })
+    }
+
+    #[test]
+    fn volume_keys_adjust_only_the_focused_session() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        backend.set_volume_of(spotify, 0.5);
+        backend.set_volume_of(mpv, 0.5);
+
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+        let mut targets = targets(Fallback::PassThrough);
+
+        handle_volume_key(VolumeKey::Up, Modifiers::NONE, &provider, &backend, &mut targets, &mut acceleration());
+        assert!(backend.volume_of(mpv) > 0.5);
+        assert_eq!(backend.volume_of(spotify), 0.5);
+
+        handle_volume_key(VolumeKey::Down, Modifiers::NONE, &provider, &backend, &mut targets, &mut acceleration());
+        handle_volume_key(VolumeKey::Down, Modifiers::NONE, &provider, &backend, &mut targets, &mut acceleration());
+        assert!(backend.volume_of(mpv) < 0.5);
+
+        handle_volume_key(VolumeKey::Mute, Modifiers::NONE, &provider, &backend, &mut targets, &mut acceleration());
+        assert!(backend.muted_of(mpv));
+        assert!(!backend.muted_of(spotify));
+    }
+
+    #[test]
+    fn volume_keys_adjust_every_session_of_the_focused_application() {
+        let backend = MockAudioBackend::new();
+        let tab = backend.add_session(10, "/usr/lib/chromium/chromium");
+        let call = backend.add_session(11, "/usr/lib/chromium/chromium");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        backend.set_volume_of(tab, 0.8);
+        backend.set_volume_of(call, 0.4);
+        backend.set_volume_of(mpv, 0.5);
+        backend.set_muted_of(call, true);
+
+        // The window belongs to the browser process, the sessions to its helpers
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(9, "/usr/lib/chromium/chromium");
+        let mut targets = targets(Fallback::PassThrough);
+
+        handle_volume_key(VolumeKey::Down, Modifiers::NONE, &provider, &backend, &mut targets, &mut acceleration());
+        assert!(backend.volume_of(tab) < 0.8);
+        assert!((backend.volume_of(call) / backend.volume_of(tab) - 0.5).abs() < 1e-4, "relative levels should be kept");
+        assert_eq!(backend.volume_of(mpv), 0.5);
+
+        // Only partly muted, so the first press mutes everything
+        handle_volume_key(VolumeKey::Mute, Modifiers::NONE, &provider, &backend, &mut targets, &mut acceleration());
+        assert!(backend.muted_of(tab) && backend.muted_of(call));
+        handle_volume_key(VolumeKey::Mute, Modifiers::NONE, &provider, &backend, &mut targets, &mut acceleration());
+        assert!(!backend.muted_of(tab) && !backend.muted_of(call));
+        assert!(!backend.muted_of(mpv));
+    }
+
+    #[test]
+    fn keys_without_a_target_follow_the_fallback() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(10, "/usr/bin/spotify");
+        backend.set_master_volume_of(0.5);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(30, "/usr/bin/gedit");
+
+        let mut passing = targets(Fallback::PassThrough);
+        assert_eq!(handle_volume_key(VolumeKey::Up, Modifiers::NONE, &provider, &backend, &mut passing, &mut acceleration()), KeyOutcome::PassThrough);
+        assert_eq!(backend.master_volume_of(), 0.5);
+
+        let mut master = targets(Fallback::Master);
+        assert_eq!(handle_volume_key(VolumeKey::Down, Modifiers::NONE, &provider, &backend, &mut master, &mut acceleration()), KeyOutcome::Handled);
+        assert!(backend.master_volume_of() < 0.5);
+        assert_eq!(handle_volume_key(VolumeKey::Mute, Modifiers::NONE, &provider, &backend, &mut master, &mut acceleration()), KeyOutcome::Handled);
+        assert!(backend.master_muted_of());
+    }
+
+    #[test]
+    fn ctrl_turns_down_everything_but_the_focused_application() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let game = backend.add_session(20, "/usr/bin/game");
+        let mpv = backend.add_session(30, "/usr/bin/mpv");
+        for session in [spotify, game, mpv] {
+            backend.set_volume_of(session, 0.5);
+        }
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/game");
+        let mut targets = targets(Fallback::PassThrough);
+        let ctrl = Modifiers { ctrl: true, ..Modifiers::NONE };
+
+        assert_eq!(handle_volume_key(VolumeKey::Down, ctrl, &provider, &backend, &mut targets, &mut acceleration()), KeyOutcome::Handled);
+        assert!(backend.volume_of(spotify) < 0.5 && backend.volume_of(mpv) < 0.5);
+        assert_eq!(backend.volume_of(game), 0.5);
+
+        handle_volume_key(VolumeKey::Mute, ctrl, &provider, &backend, &mut targets, &mut acceleration());
+        assert!(backend.muted_of(spotify) && backend.muted_of(mpv));
+        assert!(!backend.muted_of(game));
+    }
+
+    #[test]
+    fn adjustment_averages_the_configured_number_of_presses() {
+        let config = AccelerationConfig {
+            base_increment: 0.01,
+            history: 2,
+            curve: CurveConfig::Stepped(Stepped { steps: vec![Step { below_ms: 100, factor: 4.0 }], otherwise: 1.0 }),
+            ..Default::default()
+        };
+        let clock = FakeClock::new();
+        let mut state = VolumeKeyState::with_clock(&config, clock.clone());
+
+        // The gap before each press in ms, and the step it should make
+        let presses = [(0, 0.01), (50, 0.01), (50, 0.04), (50, 0.04), (1000, 0.01), (50, 0.01)];
+        for (gap, expected) in presses {
+            clock.advance(Duration::from_millis(gap));
+            let step = state.adjustment(Direction::Up);
+            assert!((step - expected).abs() < 1e-6, "press after {}ms stepped {}", gap, step);
+        }
+    }
+
+    #[test]
+    fn quick_presses_speed_up_once_the_history_is_full_of_them() {
+        let clock = FakeClock::new();
+        let mut state = VolumeKeyState::with_clock(&AccelerationConfig::default(), clock.clone());
+
+        // The first press counts as a second after the last, which keeps the
+        // average slow until five quick presses have pushed it out
+        let mut steps = vec![state.adjustment(Direction::Up)];
+        for _ in 0..5 {
+            clock.advance(Duration::from_millis(50));
+            steps.push(state.adjustment(Direction::Up));
+        }
+
+        assert_eq!(steps[..5], [0.01; 5]);
+        assert!((steps[5] - 0.15 * (-0.8f32).exp()).abs() < 1e-6, "{:?}", steps);
+    }
+
+    // Gives the steps for presses made after the given gaps in ms, going the given ways
+    fn steps(config: &AccelerationConfig, presses: &[(u64, Direction)]) -> Vec<f32> {
+        let clock = FakeClock::new();
+        let mut state = VolumeKeyState::with_clock(config, clock.clone());
+        presses.iter()
+            .map(|&(gap, direction)| {
+                clock.advance(Duration::from_millis(gap));
+                state.adjustment(direction)
+            })
+            .collect()
+    }
+
+    fn fast_up_then_down() -> Vec<(u64, Direction)> {
+        let mut presses = vec![(0, Direction::Up)];
+        presses.extend([(50, Direction::Up); 6]);
+        presses.push((50, Direction::Down));
+        presses
+    }
+
+    #[test]
+    fn reversing_direction_starts_acceleration_over() {
+        let steps = steps(&AccelerationConfig::default(), &fast_up_then_down());
+        assert!(steps[6] > 0.05, "the presses up should have accelerated: {:?}", steps);
+        assert_eq!(steps[7], 0.01, "the first press down makes the base step");
+    }
+
+    #[test]
+    fn damped_reversal_keeps_some_of_the_speed() {
+        let config = AccelerationConfig {
+            curve: CurveConfig::Linear(Linear { min: 1.0, max: 5.0, fast_ms: 0, slow_ms: 400 }),
+            on_reversal: Reversal::Damp(0.5),
+            ..Default::default()
+        };
+        let steps = steps(&config, &fast_up_then_down());
+
+        // Up averages 50ms, and down averages 90ms: four remembered 50ms presses
+        // stretched to 100ms, plus its own 50ms
+        assert!((steps[6] - 0.045).abs() < 1e-6, "{:?}", steps);
+        assert!((steps[7] - 0.041).abs() < 1e-6, "{:?}", steps);
+    }
+
+    #[test]
+    fn idle_presses_start_acceleration_over() {
+        let config = AccelerationConfig { idle_reset_ms: 500, ..Default::default() };
+        let mut presses = fast_up_then_down();
+        presses.pop();
+        presses.push((500, Direction::Up));
+        let steps = steps(&config, &presses);
+
+        assert!(steps[6] > 0.05, "{:?}", steps);
+        assert_eq!(steps[7], 0.01, "a press after the idle timeout makes the base step");
+    }
+
+    #[test]
+    fn steps_are_taken_along_the_configured_scale() {
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        backend.set_volume_of(mpv, 0.125);
+        backend.set_master_volume_of(0.125);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+        let config = AccelerationConfig { base_increment: 0.1, scale: VolumeScale::Cubic, ..Default::default() };
+        let mut acceleration = VolumeKeyState::with_clock(&config, FakeClock::new());
+
+        // 0.125 is halfway along the cubic taper, so a step up lands on 0.6 cubed
+        handle_volume_key(VolumeKey::Up, Modifiers::NONE, &provider, &backend, &mut targets(Fallback::PassThrough), &mut acceleration);
+        assert!((backend.volume_of(mpv) - 0.216).abs() < 1e-5, "{}", backend.volume_of(mpv));
+
+        let mut master = targets(Fallback::Master);
+        provider.focus_process(30, "/usr/bin/gedit");
+        handle_volume_key(VolumeKey::Down, Modifiers::NONE, &provider, &backend, &mut master, &mut acceleration);
+        assert!((backend.master_volume_of() - 0.064).abs() < 1e-5, "{}", backend.master_volume_of());
+    }
+}
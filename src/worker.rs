@@ -0,0 +1,522 @@
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use crate::audio::AudioBackend;
+use crate::config::Config;
+use crate::controller::Controller;
+use crate::error::Result;
+use crate::focus::FocusProvider;
+use crate::keyboard::Modifiers;
+use crate::volume::VolumeKey;
+
+/// Work queued by an input source for the worker thread
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Key(VolumeKey, Modifiers),
+    /// A volume key was let go, which ends a hold and tells a long press of mute from a tap
+    Release(VolumeKey),
+    /// Pin the volume keys to the focused application, or unpin them
+    TogglePin,
+    /// Mute everything but the focused application, or put the mute states back
+    ToggleSolo,
+    Shutdown,
+}
+
+/// Queues commands for a `Worker` without ever waiting on it.
+///
+/// This is what input callbacks such as the low-level keyboard hook hold on
+/// to; the channel is unbounded, so sending never blocks on audio work.
+#[derive(Clone)]
+pub struct CommandSender {
+    tx: mpsc::Sender<Command>,
+}
+
+impl CommandSender {
+    /// Queues a volume key press. Returns false if the worker has stopped,
+    /// in which case the caller should let the key through.
+    pub fn send(&self, key: VolumeKey) -> bool {
+        self.send_with_modifiers(key, Modifiers::NONE)
+    }
+
+    /// Like `send`, for a press made while holding modifier keys
+    pub fn send_with_modifiers(&self, key: VolumeKey, modifiers: Modifiers) -> bool {
+        self.tx.send(Command::Key(key, modifiers)).is_ok()
+    }
+
+    /// Queues the release of a volume key whose press was queued
+    pub fn release(&self, key: VolumeKey) -> bool {
+        self.tx.send(Command::Release(key)).is_ok()
+    }
+
+    /// Queues a pin toggle. Returns false if the worker has stopped.
+    pub fn toggle_pin(&self) -> bool {
+        self.tx.send(Command::TogglePin).is_ok()
+    }
+
+    /// Queues a solo toggle. Returns false if the worker has stopped.
+    pub fn toggle_solo(&self) -> bool {
+        self.tx.send(Command::ToggleSolo).is_ok()
+    }
+}
+
+/// A dedicated thread that performs the focus lookups and volume changes
+pub struct Worker {
+    commands: CommandSender,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Starts the worker thread.
+    ///
+    /// `make_backend` runs on the worker thread, since backends such as
+    /// WASAPI are tied to the thread that created them. The backend is
+    /// created once, reused for every command and dropped when the worker
+    /// shuts down; creation is only retried if it failed, and the backend is
+    /// only replaced when it reports that the default output device changed.
+    /// Each backend gets its own session matcher, set up from `config` and
+    /// kept up to date between key presses.
+    ///
+    /// Keys with nothing to adjust, including those arriving while there is
+    /// no backend, are handed to `pass_through` to be sent on to the system.
+    pub fn spawn<F, B, MB, P>(provider: F, make_backend: MB, config: Config, pass_through: P) -> Self
+    where
+        F: FocusProvider + Send + 'static,
+        B: AudioBackend,
+        MB: Fn() -> Result<B> + Send + 'static,
+        P: Fn(VolumeKey) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let thread = std::thread::spawn(move || run(provider, make_backend, config, pass_through, rx));
+
+        Self {
+            commands: CommandSender { tx },
+            thread: Some(thread),
+        }
+    }
+
+    pub fn sender(&self) -> CommandSender {
+        self.commands.clone()
+    }
+
+    /// Finishes the commands already queued and stops the thread
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.commands.tx.send(Command::Shutdown);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run<F: FocusProvider, B: AudioBackend>(
+    provider: F,
+    make_backend: impl Fn() -> Result<B>,
+    config: Config,
+    pass_through: impl Fn(VolumeKey),
+    commands: mpsc::Receiver<Command>,
+) {
+    let mut backend = open_backend(&make_backend, &config);
+
+    while let Some(command) = next_command(&commands, &provider, backend.as_mut().map(|(backend, controller)| (&*backend, controller))) {
+        if backend.as_ref().is_some_and(|(backend, _)| backend.device_changed()) {
+            println!("Default output device changed, reopening audio backend");
+            backend = reopen_backend(&make_backend, &config, backend.take());
+        }
+        if backend.is_none() {
+            backend = open_backend(&make_backend, &config);
+        }
+
+        let passed = match (&mut backend, command) {
+            (Some((backend, controller)), command) => controller.handle(command, &provider, backend),
+            (None, Command::Key(key, _)) => Some(key),
+            (None, _) => None,
+        };
+        if let Some(key) = passed {
+            pass_through(key);
+        }
+    }
+
+    // The backend is dropped here, on the thread that created it
+}
+
+/// Waits for the next command for `controller`, letting it ramp held keys and
+/// look at focus changes in the meantime. Returns `None` once every sender is
+/// gone or a shutdown was queued.
+pub fn next_command<F: FocusProvider, B: AudioBackend>(
+    commands: &mpsc::Receiver<Command>,
+    provider: &F,
+    mut active: Option<(&B, &mut Controller<B::Session>)>,
+) -> Option<Command> {
+    loop {
+        let command = match active.as_ref().and_then(|(_, controller)| controller.next_wake()) {
+            Some(timeout) => match commands.recv_timeout(timeout) {
+                Ok(command) => command,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if let Some((backend, controller)) = &mut active {
+                        controller.wake(provider, *backend);
+                    }
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            },
+            None => commands.recv().ok()?,
+        };
+        return (command != Command::Shutdown).then_some(command);
+    }
+}
+
+fn open_backend<B: AudioBackend>(make_backend: &impl Fn() -> Result<B>, config: &Config) -> Option<(B, Controller<B::Session>)> {
+    match make_backend() {
+        Ok(backend) => Some((backend, Controller::new(config))),
+        Err(e) => {
+            println!("Error opening audio backend: {}", e);
+            None
+        }
+    }
+}
+
+// Replaces a backend bound to an old output device, keeping the pin
+fn reopen_backend<B: AudioBackend>(
+    make_backend: &impl Fn() -> Result<B>,
+    config: &Config,
+    old: Option<(B, Controller<B::Session>)>,
+) -> Option<(B, Controller<B::Session>)> {
+    let pinned = old.and_then(|(_, mut controller)| controller.targets().pinned().map(str::to_string));
+    let mut backend = open_backend(make_backend, config);
+    if let (Some((_, controller)), Some(path)) = (&mut backend, pinned) {
+        controller.targets().pin(path);
+    }
+    backend
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::Duration;
+    use crate::audio::{MockAudioBackend, MockSession};
+    use crate::focus::FakeFocusProvider;
+
+    // Shared counters and a gate that can hold volume reads until the test opens it
+    #[derive(Clone)]
+    struct Instruments {
+        gate: Arc<(Mutex<bool>, Condvar)>,
+        waiting: Arc<AtomicUsize>,
+        created: Arc<AtomicUsize>,
+        drops: Arc<AtomicUsize>,
+        // Bumped when the default output device changes
+        device: Arc<AtomicUsize>,
+    }
+
+    impl Instruments {
+        fn new(open: bool) -> Self {
+            Self {
+                gate: Arc::new((Mutex::new(open), Condvar::new())),
+                waiting: Arc::new(AtomicUsize::new(0)),
+                created: Arc::new(AtomicUsize::new(0)),
+                drops: Arc::new(AtomicUsize::new(0)),
+                device: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        fn open(&self) {
+            let (open, cvar) = &*self.gate;
+            *open.lock().unwrap() = true;
+            cvar.notify_all();
+        }
+
+        // Spawns a worker whose backend factory creates instrumented mock backends
+        fn spawn_worker(&self, inner: &MockAudioBackend, provider: FakeFocusProvider) -> Worker {
+            let inner = inner.clone();
+            let instruments = self.clone();
+            let make_backend = move || {
+                instruments.created.fetch_add(1, Ordering::SeqCst);
+                let device = instruments.device.load(Ordering::SeqCst);
+                Ok(InstrumentedBackend { inner: inner.clone(), instruments: instruments.clone(), device })
+            };
+            Worker::spawn(provider, make_backend, Config::default(), |_| {})
+        }
+    }
+
+    struct InstrumentedBackend {
+        inner: MockAudioBackend,
+        instruments: Instruments,
+        // The output device that was the default when this was opened
+        device: usize,
+    }
+
+    impl Drop for InstrumentedBackend {
+        fn drop(&mut self) {
+            self.instruments.drops.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl AudioBackend for InstrumentedBackend {
+        type Session = MockSession;
+
+        fn sessions(&self) -> Result<Vec<MockSession>> {
+            self.inner.sessions()
+        }
+
+        fn session_pid(&self, session: &MockSession) -> Result<u32> {
+            self.inner.session_pid(session)
+        }
+
+        fn session_path(&self, session: &MockSession) -> Result<String> {
+            self.inner.session_path(session)
+        }
+
+        fn volume(&self, session: &MockSession) -> Result<f32> {
+            self.instruments.waiting.fetch_add(1, Ordering::SeqCst);
+            let (open, cvar) = &*self.instruments.gate;
+            let _open = cvar.wait_while(open.lock().unwrap(), |open| !*open).unwrap();
+            self.inner.volume(session)
+        }
+
+        fn set_volume(&self, session: &MockSession, volume: f32) -> Result<()> {
+            self.inner.set_volume(session, volume)
+        }
+
+        fn is_muted(&self, session: &MockSession) -> Result<bool> {
+            self.inner.is_muted(session)
+        }
+
+        fn set_muted(&self, session: &MockSession, muted: bool) -> Result<()> {
+            self.inner.set_muted(session, muted)
+        }
+
+        fn watch_sessions(&self) -> Result<bool> {
+            self.inner.watch_sessions()
+        }
+
+        fn session_events(&self) -> Result<Vec<crate::audio::SessionEvent<MockSession>>> {
+            self.inner.session_events()
+        }
+
+        fn device_changed(&self) -> bool {
+            self.instruments.device.load(Ordering::SeqCst) != self.device
+        }
+    }
+
+    #[test]
+    fn worker_applies_queued_keys() {
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        backend.set_volume_of(mpv, 0.5);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+
+        let worker = {
+            let backend = backend.clone();
+            Worker::spawn(provider, move || Ok(backend.clone()), Config::default(), |_| {})
+        };
+        let commands = worker.sender();
+        assert!(commands.send(VolumeKey::Up));
+        assert!(commands.send(VolumeKey::Mute));
+        worker.shutdown();
+
+        assert!(backend.volume_of(mpv) > 0.5);
+        assert!(backend.muted_of(mpv));
+        assert!(!commands.send(VolumeKey::Up), "sending after shutdown should report the worker is gone");
+    }
+
+    #[test]
+    fn worker_picks_up_sessions_started_later_without_rescanning() {
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        backend.set_volume_of(mpv, 0.5);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+
+        let worker = {
+            let backend = backend.clone();
+            Worker::spawn(provider.clone(), move || Ok(backend.clone()), Config::default(), |_| {})
+        };
+        let commands = worker.sender();
+        assert!(commands.send(VolumeKey::Down));
+
+        let vlc = backend.add_session(30, "/usr/bin/vlc");
+        backend.set_volume_of(vlc, 0.5);
+        provider.focus_process(30, "/usr/bin/vlc");
+        assert!(commands.send(VolumeKey::Mute));
+        worker.shutdown();
+
+        assert!(backend.muted_of(vlc));
+        assert_eq!(backend.scan_count(), 1, "later presses should use the session registry");
+    }
+
+    #[test]
+    fn sending_never_waits_for_audio_calls() {
+        let inner = MockAudioBackend::new();
+        inner.add_session(20, "/usr/bin/mpv");
+        let instruments = Instruments::new(false);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+
+        let worker = instruments.spawn_worker(&inner, provider);
+        let commands = worker.sender();
+
+        // Queue presses from another thread while the worker is stuck in the backend
+        let (done_tx, done_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for _ in 0..100 {
+                assert!(commands.send(VolumeKey::Up));
+            }
+            done_tx.send(()).unwrap();
+        });
+
+        assert!(done_rx.recv_timeout(Duration::from_secs(1)).is_ok(), "sending blocked on the worker");
+        assert!(instruments.waiting.load(Ordering::SeqCst) <= 1);
+
+        instruments.open();
+        worker.shutdown();
+        assert_eq!(instruments.waiting.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn backend_is_created_once_reused_and_dropped_at_shutdown() {
+        let inner = MockAudioBackend::new();
+        let mpv = inner.add_session(20, "/usr/bin/mpv");
+        inner.set_volume_of(mpv, 0.5);
+        let instruments = Instruments::new(true);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+
+        let worker = instruments.spawn_worker(&inner, provider);
+        let commands = worker.sender();
+        for _ in 0..10 {
+            assert!(commands.send(VolumeKey::Down));
+        }
+        worker.shutdown();
+
+        assert_eq!(instruments.created.load(Ordering::SeqCst), 1);
+        assert_eq!(instruments.waiting.load(Ordering::SeqCst), 10);
+        assert_eq!(instruments.drops.load(Ordering::SeqCst), 1, "the worker's backend should be dropped exactly once");
+        assert!(inner.volume_of(mpv) < 0.5);
+    }
+
+    #[test]
+    fn backend_is_reopened_when_the_default_device_changes() {
+        let inner = MockAudioBackend::new();
+        let mpv = inner.add_session(20, "/usr/bin/mpv");
+        inner.set_volume_of(mpv, 0.5);
+        let instruments = Instruments::new(true);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+
+        let worker = instruments.spawn_worker(&inner, provider.clone());
+        let commands = worker.sender();
+        assert!(commands.toggle_pin());
+        assert!(commands.send(VolumeKey::Down));
+        // Once the key has been handled, so has the pin before it
+        while inner.volume_of(mpv) == 0.5 {
+            std::thread::yield_now();
+        }
+        let before = inner.volume_of(mpv);
+
+        // The pin outlives the old backend, so the keys still reach mpv without focus
+        provider.focus_process(30, "/usr/bin/gedit");
+        instruments.device.fetch_add(1, Ordering::SeqCst);
+        assert!(commands.send(VolumeKey::Down));
+        assert!(commands.send(VolumeKey::Down));
+        worker.shutdown();
+
+        assert_eq!(instruments.created.load(Ordering::SeqCst), 2, "the backend should be reopened once");
+        assert_eq!(instruments.drops.load(Ordering::SeqCst), 2);
+        assert!(inner.volume_of(mpv) < before);
+    }
+
+    #[test]
+    fn backend_creation_is_retried_after_failure() {
+        let backend = MockAudioBackend::new();
+        backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(20, "/usr/bin/mpv");
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let worker = {
+            let backend = backend.clone();
+            let attempts = attempts.clone();
+            let make_backend = move || {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(crate::error::Error::Backend("no audio device".into()))
+                } else {
+                    Ok(backend.clone())
+                }
+            };
+            Worker::spawn(provider, make_backend, Config::default(), |_| {})
+        };
+
+        let commands = worker.sender();
+        assert!(commands.send(VolumeKey::Mute));
+        assert!(commands.send(VolumeKey::Mute));
+        worker.shutdown();
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert!(!backend.muted_of(backend.sessions().unwrap()[0]), "both presses should toggle mute");
+    }
+
+    #[test]
+    fn keys_with_nothing_to_adjust_are_passed_through() {
+        let backend = MockAudioBackend::new();
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(30, "/usr/bin/gedit");
+
+        let (passed_tx, passed_rx) = mpsc::channel();
+        let worker = {
+            let backend = backend.clone();
+            let passed_tx = Mutex::new(passed_tx);
+            Worker::spawn(provider, move || Ok(backend.clone()), Config::default(), move |key| {
+                passed_tx.lock().unwrap().send(key).unwrap();
+            })
+        };
+        let commands = worker.sender();
+        assert!(commands.send(VolumeKey::Down));
+        assert!(commands.send(VolumeKey::Mute));
+        worker.shutdown();
+
+        assert_eq!(passed_rx.try_iter().collect::<Vec<_>>(), vec![VolumeKey::Down, VolumeKey::Mute]);
+        assert!(!backend.muted_of(mpv));
+    }
+
+    #[test]
+    fn pinned_application_keeps_the_keys_after_focus_moves() {
+        let backend = MockAudioBackend::new();
+        let spotify = backend.add_session(10, "/usr/bin/spotify");
+        let mpv = backend.add_session(20, "/usr/bin/mpv");
+        backend.set_volume_of(spotify, 0.5);
+        let provider = FakeFocusProvider::new();
+        provider.focus_process(10, "/usr/bin/spotify");
+
+        let worker = {
+            let backend = backend.clone();
+            Worker::spawn(provider.clone(), move || Ok(backend.clone()), Config::default(), |_| {})
+        };
+        let commands = worker.sender();
+        assert!(commands.toggle_pin());
+        assert!(commands.send(VolumeKey::Up));
+
+        // Commands run in order, so once the volume moved the pin is in place
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while backend.volume_of(spotify) == 0.5 {
+            assert!(std::time::Instant::now() < deadline, "the worker never handled the key");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        provider.focus_process(20, "/usr/bin/mpv");
+        assert!(commands.send(VolumeKey::Mute));
+        worker.shutdown();
+
+        assert!(backend.muted_of(spotify));
+        assert!(!backend.muted_of(mpv));
+    }
+}